@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+/// Everything the viewer can be told to do from the keyboard.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Sprint,
+    RollLeft,
+    RollRight,
+    CycleProjection,
+    ToggleLookMode,
+    ToggleController,
+    GrabCursor,
+    RecordKeyframe,
+    PlayPath,
+    ClearPath,
+    ExposureUp,
+    ExposureDown,
+    CycleToneMapper,
+    CycleAov,
+    ToggleDenoiser,
+    ToggleTemporal,
+    ToggleBloom,
+    ToggleVignette,
+    ToggleLens,
+    ToggleGrain,
+    Screenshot,
+    Quit,
+}
+
+impl Action {
+    /// Actions that last as long as their key is held.
+    pub fn is_held(self) -> bool {
+        use Action::*;
+        matches!(self, MoveForward | MoveBackward | MoveLeft | MoveRight | MoveUp | MoveDown | Sprint)
+    }
+}
+
+const DEFAULT_BINDINGS: &[(Action, &[VirtualKeyCode])] = {
+    use Action::*;
+    use VirtualKeyCode as Key;
+    &[
+        (MoveForward, &[Key::W, Key::Up]),
+        (MoveBackward, &[Key::S, Key::Down]),
+        (MoveLeft, &[Key::A, Key::Left]),
+        (MoveRight, &[Key::D, Key::Right]),
+        (MoveUp, &[Key::E]),
+        (MoveDown, &[Key::Q]),
+        (Sprint, &[Key::LShift, Key::RShift]),
+        (RollLeft, &[Key::Z]),
+        (RollRight, &[Key::X]),
+        (CycleProjection, &[Key::P]),
+        (ToggleLookMode, &[Key::L]),
+        (ToggleController, &[Key::Tab]),
+        (GrabCursor, &[Key::G]),
+        (RecordKeyframe, &[Key::K]),
+        (PlayPath, &[Key::Space]),
+        (ClearPath, &[Key::Back]),
+        (ExposureUp, &[Key::Equals, Key::NumpadAdd]),
+        (ExposureDown, &[Key::Minus, Key::NumpadSubtract]),
+        (CycleToneMapper, &[Key::T]),
+        (CycleAov, &[Key::V]),
+        (ToggleDenoiser, &[Key::N]),
+        (ToggleTemporal, &[Key::H]),
+        (ToggleBloom, &[Key::B]),
+        (ToggleVignette, &[Key::O]),
+        (ToggleLens, &[Key::C]),
+        (ToggleGrain, &[Key::F]),
+        (Screenshot, &[Key::F12]),
+        (Quit, &[Key::Escape]),
+    ]
+};
+
+/// Which key triggers which action. Key names are winit's `VirtualKeyCode`
+/// variants, which name keys by the character they produce, so layouts
+/// other than QWERTY usually want to rebind the movement keys.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    actions: HashMap<VirtualKeyCode, Action>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    bindings: HashMap<Action, Vec<VirtualKeyCode>>,
+}
+
+impl KeyBindings {
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+
+    /// Parses a config file with a `[bindings]` table of action names to
+    /// lists of keys:
+    ///
+    /// ```toml
+    /// [bindings]
+    /// move_forward = ["Z", "Up"]
+    /// move_left = ["Q", "Left"]
+    /// ```
+    ///
+    /// Listed actions replace their default keys, the rest keep them. A key
+    /// listed in the file is taken away from whichever action it was bound
+    /// to by default, but listing it under two actions is an error.
+    pub fn from_toml(text: &str) -> Result<KeyBindings> {
+        let file: ConfigFile = toml::from_str(text)?;
+
+        let mut actions: HashMap<VirtualKeyCode, Action> = DEFAULT_BINDINGS
+            .iter()
+            .filter(|(action, _)| !file.bindings.contains_key(action))
+            .flat_map(|&(action, keys)| keys.iter().map(move |&key| (key, action)))
+            .collect();
+
+        // Sorted so that conflicts are reported the same way every time.
+        let mut listed: Vec<_> = file.bindings.into_iter().collect();
+        listed.sort_by_key(|&(action, _)| action);
+        let mut user = HashMap::new();
+        for (action, keys) in listed {
+            for key in keys {
+                match user.insert(key, action) {
+                    Some(other) if other != action => {
+                        bail!("{:?} is bound to both {:?} and {:?}", key, other, action)
+                    }
+                    _ => {
+                        actions.insert(key, action);
+                    }
+                }
+            }
+        }
+        Ok(KeyBindings { actions })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<KeyBindings> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        KeyBindings::from_toml(&text).with_context(|| format!("loading {}", path.display()))
+    }
+
+    /// Loads `path` if it exists and falls back to the defaults otherwise.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<KeyBindings> {
+        if path.as_ref().exists() {
+            KeyBindings::load(path)
+        } else {
+            Ok(KeyBindings::default())
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings::from_toml("").expect("default bindings have no conflicts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_bind_wasd_and_arrows() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.action(VirtualKeyCode::W), Some(Action::MoveForward));
+        assert_eq!(bindings.action(VirtualKeyCode::Up), Some(Action::MoveForward));
+        assert_eq!(bindings.action(VirtualKeyCode::Escape), Some(Action::Quit));
+        assert_eq!(bindings.action(VirtualKeyCode::F1), None);
+    }
+
+    #[test]
+    fn config_replaces_the_keys_of_listed_actions() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            [bindings]
+            move_forward = ["Z"]
+            roll_left = ["R"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::Z), Some(Action::MoveForward));
+        assert_eq!(bindings.action(VirtualKeyCode::W), None);
+        assert_eq!(bindings.action(VirtualKeyCode::R), Some(Action::RollLeft));
+        assert_eq!(bindings.action(VirtualKeyCode::S), Some(Action::MoveBackward));
+    }
+
+    #[test]
+    fn listed_keys_are_taken_from_the_defaults() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            [bindings]
+            move_forward = ["Z", "Up"]
+            move_left = ["Q", "Left"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::Z), Some(Action::MoveForward));
+        assert_eq!(bindings.action(VirtualKeyCode::Q), Some(Action::MoveLeft));
+        assert_eq!(bindings.action(VirtualKeyCode::X), Some(Action::RollRight));
+        assert_eq!(bindings.action(VirtualKeyCode::E), Some(Action::MoveUp));
+    }
+
+    #[test]
+    fn config_errors_are_reported() {
+        assert!(KeyBindings::from_toml("[bindings]\njump = [\"Space\"]").is_err());
+        assert!(KeyBindings::from_toml("[bindings]\nquit = [\"NotAKey\"]").is_err());
+        let error = KeyBindings::from_toml("[bindings]\nroll_left = [\"Z\"]\nmove_forward = [\"Z\"]").unwrap_err();
+        assert_eq!(error.to_string(), "Z is bound to both MoveForward and RollLeft");
+    }
+}
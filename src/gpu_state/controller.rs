@@ -0,0 +1,453 @@
+use std::collections::HashSet;
+use std::f32::consts::{FRAC_PI_2, TAU};
+use cgmath::{Vector3, InnerSpace, Zero};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta};
+use super::bindings::Action;
+use super::pipeline::camera::Camera;
+
+/// Below this speed the camera is considered at rest, so the accumulated
+/// image is not reset forever by tiny movements.
+const REST_SPEED: f32 = 1e-3;
+
+/// Free-fly movement driven by held movement actions, by default WASD or the
+/// arrow keys along the view, Q and E down and up, and shift to sprint.
+/// Movement is scaled by the elapsed time, so the speed does not depend on
+/// the frame rate or the key repeat rate.
+pub struct FlyController {
+    pub movement: Movement,
+    held: HashSet<Action>,
+    // Forwards, right and up in the camera's frame
+    velocity: Vector3<f32>,
+}
+
+/// How fast the fly controller moves and how quickly it gets up to speed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Movement {
+    /// Top speed in scene units per second.
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// How quickly the camera reaches its top speed, per second.
+    pub acceleration: f32,
+    /// How quickly the camera comes to rest once all keys are released, per
+    /// second.
+    pub damping: f32,
+}
+
+impl Default for Movement {
+    fn default() -> Movement {
+        Movement {
+            speed: 3.0,
+            sprint_multiplier: 4.0,
+            acceleration: 10.0,
+            damping: 8.0,
+        }
+    }
+}
+
+impl FlyController {
+    pub fn new(movement: Movement) -> FlyController {
+        FlyController {
+            movement,
+            held: HashSet::new(),
+            velocity: Vector3::zero(),
+        }
+    }
+
+    /// Starts a held action, others are ignored. Returns whether the
+    /// action was used.
+    pub fn press(&mut self, action: Action) -> bool {
+        action.is_held() && {
+            self.held.insert(action);
+            true
+        }
+    }
+
+    pub fn release(&mut self, action: Action) -> bool {
+        self.held.remove(&action)
+    }
+
+    /// Forgets all held actions, for when the window loses focus and the
+    /// release events go elsewhere.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Direction the held keys ask for, in the camera's frame.
+    fn wish_direction(&self) -> Vector3<f32> {
+        use Action::*;
+        let axis = |positive, negative| {
+            self.held.contains(&positive) as i32 as f32 - self.held.contains(&negative) as i32 as f32
+        };
+        let direction = Vector3::new(
+            axis(MoveForward, MoveBackward),
+            axis(MoveRight, MoveLeft),
+            axis(MoveUp, MoveDown),
+        );
+        if direction.is_zero() { direction } else { direction.normalize() }
+    }
+
+    /// Advances the camera by `dt` seconds. Returns whether it moved.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        let direction = self.wish_direction();
+        let Movement { speed, sprint_multiplier, acceleration, damping } = self.movement;
+        let speed = if self.held.contains(&Action::Sprint) { speed * sprint_multiplier } else { speed };
+
+        // Exponential approach to the target velocity, which gives the same
+        // path for any split of the elapsed time
+        let rate = if direction.is_zero() { damping } else { acceleration };
+        let target = speed * direction;
+        let blend = 1.0 - (-rate * dt).exp();
+        let previous = self.velocity;
+        self.velocity += blend * (target - self.velocity);
+
+        if direction.is_zero() && self.velocity.magnitude() < REST_SPEED {
+            self.velocity = Vector3::zero();
+        }
+        if self.velocity.is_zero() && previous.is_zero() {
+            return false;
+        }
+
+        // Exact integral of the velocity over the step
+        let distance = if rate > 0.0 {
+            target * dt + (previous - target) * blend / rate
+        } else {
+            previous * dt
+        };
+        camera.translate(distance.into());
+        true
+    }
+}
+
+impl Default for FlyController {
+    fn default() -> FlyController {
+        FlyController::new(Movement::default())
+    }
+}
+
+/// Turns the camera from relative mouse motion, which keeps working at the
+/// edges of the window while the cursor is grabbed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MouseLook {
+    /// Degrees turned per count of mouse motion.
+    pub sensitivity: f32,
+    /// Moving the mouse down looks up.
+    pub invert_y: bool,
+}
+
+impl MouseLook {
+    /// `delta` is the raw motion reported by the mouse, positive to the
+    /// right and down.
+    pub fn rotate(&self, camera: &mut Camera, [dx, dy]: [f64; 2]) {
+        let turns_per_count = self.sensitivity / 360.0;
+        let dy = if self.invert_y { dy } else { -dy };
+        camera.rotate_rightwards(dx as f32 * turns_per_count);
+        camera.rotate_upwards(dy as f32 * turns_per_count);
+    }
+}
+
+impl Default for MouseLook {
+    fn default() -> MouseLook {
+        MouseLook {
+            sensitivity: 0.1,
+            invert_y: false,
+        }
+    }
+}
+
+/// Pitch is kept this far from the poles, where the orbit has no defined
+/// right vector.
+const MAX_ORBIT_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Closest the camera can dolly to the target.
+const MIN_ORBIT_DISTANCE: f32 = 1e-2;
+
+/// Drags shorter than this fraction of the window are clicks, so a slightly
+/// shaky hand can still click to focus.
+const MAX_CLICK_MOTION: f32 = 0.005;
+
+/// What a mouse button did to the orbit controller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ButtonUse {
+    /// The controller has no use for the button.
+    Ignored,
+    /// The button started or ended a drag.
+    Drag,
+    /// The left button was released without dragging, which is left to
+    /// click-to-focus.
+    Click,
+}
+
+/// Turntable controller in the style of modelling tools: dragging with the
+/// left button orbits around a target point, the middle button pans and the
+/// scroll wheel dollies. Mouse motion without a held button does nothing.
+pub struct OrbitController {
+    pub target: [f32; 3],
+    pub distance: f32,
+    /// Radians around the vertical axis, zero looks along x.
+    yaw: f32,
+    /// Radians above the horizon the camera looks at the target from.
+    pitch: f32,
+    /// Fraction of the distance each scroll line dollies by.
+    pub dolly_speed: f32,
+    orbiting: bool,
+    panning: bool,
+    // Cursor motion since the left button was pressed
+    dragged: f32,
+}
+
+impl OrbitController {
+    /// Orbits around the point the camera is focused on, so switching to
+    /// the orbit controller does not move the camera.
+    pub fn around(camera: &Camera) -> OrbitController {
+        let direction = Vector3::from(camera.direction());
+        let distance = camera.focus_distance().max(MIN_ORBIT_DISTANCE);
+        let target = Vector3::from(camera.position()) + distance * direction;
+
+        OrbitController {
+            target: target.into(),
+            distance,
+            yaw: direction.y.atan2(direction.x),
+            pitch: direction.z.atan2(direction.x.hypot(direction.y)).clamp(-MAX_ORBIT_PITCH, MAX_ORBIT_PITCH),
+            dolly_speed: 0.1,
+            orbiting: false,
+            panning: false,
+            dragged: 0.0,
+        }
+    }
+
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) -> ButtonUse {
+        let pressed = state == ElementState::Pressed;
+        match button {
+            MouseButton::Left if pressed => {
+                self.orbiting = true;
+                self.dragged = 0.0;
+            }
+            MouseButton::Left => {
+                let clicked = self.orbiting && self.dragged < MAX_CLICK_MOTION;
+                self.orbiting = false;
+                if clicked {
+                    return ButtonUse::Click;
+                }
+            }
+            MouseButton::Middle => self.panning = pressed,
+            _ => return ButtonUse::Ignored,
+        }
+        ButtonUse::Drag
+    }
+
+    /// Cursor movement as a fraction of the window size. Returns whether a
+    /// drag used it.
+    pub fn process_cursor(&mut self, camera: &Camera, aspect: f32, [dx, dy]: [f32; 2]) -> bool {
+        if self.orbiting {
+            self.dragged += dx.hypot(dy);
+            self.orbit(dx, dy);
+        } else if self.panning {
+            self.pan(camera, aspect, dx, dy);
+        }
+        self.orbiting || self.panning
+    }
+
+    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        let lines = match *delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            // Roughly one line per twenty pixels on touchpads
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+        };
+        self.dolly(lines);
+    }
+
+    /// Forgets held buttons, for when the window loses focus and the
+    /// release events go elsewhere.
+    pub fn release_all(&mut self) {
+        self.orbiting = false;
+        self.panning = false;
+    }
+
+    /// Dragging across the whole window turns half way around.
+    pub fn orbit(&mut self, dx: f32, dy: f32) {
+        self.yaw = (self.yaw - 0.5 * TAU * dx) % TAU;
+        self.pitch = (self.pitch - 0.5 * TAU * dy).clamp(-MAX_ORBIT_PITCH, MAX_ORBIT_PITCH);
+    }
+
+    /// Moves the target in the image plane so it follows the cursor at the
+    /// target's distance.
+    pub fn pan(&mut self, camera: &Camera, aspect: f32, dx: f32, dy: f32) {
+        let (_, right, up) = camera.axes();
+        let [half_width, half_height] = camera.half_extent_at(self.distance, aspect);
+        let offset = -2.0 * dx * half_width * Vector3::from(right) + 2.0 * dy * half_height * Vector3::from(up);
+        self.target = (Vector3::from(self.target) + offset).into();
+    }
+
+    /// Positive `lines` move closer to the target.
+    pub fn dolly(&mut self, lines: f32) {
+        self.distance = (self.distance * (-self.dolly_speed * lines).exp()).max(MIN_ORBIT_DISTANCE);
+    }
+
+    fn direction(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        )
+    }
+
+    /// Places the camera on the orbit, looking at the target.
+    pub fn update(&self, camera: &mut Camera) {
+        let direction = self.direction();
+        let position = Vector3::from(self.target) - self.distance * direction;
+        camera.set_view(position.into(), direction.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fly(controller: &mut FlyController, camera: &mut Camera, seconds: f32, fps: u32) {
+        for _ in 0..(seconds * fps as f32).round() as u32 {
+            controller.update(camera, 1.0 / fps as f32);
+        }
+    }
+
+    #[test]
+    fn distance_does_not_depend_on_the_frame_rate() {
+        let positions = [15, 60, 240].map(|fps| {
+            let mut controller = FlyController::default();
+            let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+            controller.press(Action::MoveForward);
+            fly(&mut controller, &mut camera, 1.0, fps);
+            controller.release(Action::MoveForward);
+            fly(&mut controller, &mut camera, 1.0, fps);
+            camera.position()[0]
+        });
+
+        assert!(positions[0] > 2.0);
+        for position in positions {
+            assert!((position - positions[1]).abs() < 1e-3, "{:?}", positions);
+        }
+    }
+
+    #[test]
+    fn camera_comes_to_rest_after_release() {
+        let mut controller = FlyController::default();
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        controller.press(Action::MoveUp);
+        controller.press(Action::Sprint);
+        fly(&mut controller, &mut camera, 0.5, 60);
+        assert!(camera.position()[2] > 3.0, "sprinting moves faster than walking");
+
+        controller.release_all();
+        fly(&mut controller, &mut camera, 3.0, 60);
+        assert!(!controller.update(&mut camera, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn speed_follows_the_movement_settings() {
+        let positions = [3.0, 6.0].map(|speed| {
+            let mut controller = FlyController::new(Movement { speed, ..Movement::default() });
+            let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+            controller.press(Action::MoveForward);
+            fly(&mut controller, &mut camera, 2.0, 60);
+            camera.position()[0]
+        });
+        assert!((positions[1] / positions[0] - 2.0).abs() < 1e-3, "{:?}", positions);
+
+        let mut sluggish = FlyController::new(Movement { acceleration: 1.0, ..Movement::default() });
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        sluggish.press(Action::MoveForward);
+        fly(&mut sluggish, &mut camera, 1.0, 60);
+        assert!(camera.position()[0] < 1.5, "slower acceleration takes longer to get going: {:?}", camera.position());
+    }
+
+    fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+        (Vector3::from(a) - Vector3::from(b)).magnitude()
+    }
+
+    #[test]
+    fn switching_to_orbit_keeps_the_view() {
+        let mut camera = Camera::new([1.0, 2.0, 3.0], [1.0, 1.0, -0.5]).with_lens(0.0, 5.0, 0);
+        let before = camera.into_uniform(1.0);
+        let orbit = OrbitController::around(&camera);
+        orbit.update(&mut camera);
+        let after = camera.into_uniform(1.0);
+
+        for (a, b) in bytemuck::cast_slice::<_, f32>(&[before]).iter().zip(bytemuck::cast_slice::<_, f32>(&[after])) {
+            assert!((a - b).abs() < 1e-5, "{:?} became {:?}", before, after);
+        }
+    }
+
+    #[test]
+    fn orbit_only_moves_on_drags_and_keeps_the_distance() {
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let mut orbit = OrbitController::around(&camera);
+        let target = orbit.target;
+
+        assert!(!orbit.process_cursor(&camera, 1.0, [0.25, 0.0]));
+        orbit.update(&mut camera);
+        assert_eq!(camera.position(), [0.0, 0.0, 0.0]);
+
+        orbit.process_mouse_button(MouseButton::Left, ElementState::Pressed);
+        assert!(orbit.process_cursor(&camera, 1.0, [0.25, 0.1]));
+        orbit.update(&mut camera);
+        assert!((distance(camera.position(), target) - 10.0).abs() < 1e-4);
+        assert!(camera.position()[2] > 0.0, "dragging down looks from above");
+
+        // Dragging far past the pole stops short of it
+        orbit.process_cursor(&camera, 1.0, [0.0, 5.0]);
+        orbit.update(&mut camera);
+        assert!(camera.direction().iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn left_clicks_without_a_drag_are_left_to_focusing() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let mut orbit = OrbitController::around(&camera);
+
+        assert_eq!(orbit.process_mouse_button(MouseButton::Left, ElementState::Pressed), ButtonUse::Drag);
+        orbit.process_cursor(&camera, 1.0, [0.001, 0.0]);
+        assert_eq!(orbit.process_mouse_button(MouseButton::Left, ElementState::Released), ButtonUse::Click);
+
+        orbit.process_mouse_button(MouseButton::Left, ElementState::Pressed);
+        orbit.process_cursor(&camera, 1.0, [0.1, 0.0]);
+        assert_eq!(orbit.process_mouse_button(MouseButton::Left, ElementState::Released), ButtonUse::Drag);
+
+        assert_eq!(orbit.process_mouse_button(MouseButton::Right, ElementState::Pressed), ButtonUse::Ignored);
+        // A release whose press went to the fly controller is not a click
+        assert_eq!(orbit.process_mouse_button(MouseButton::Left, ElementState::Released), ButtonUse::Drag);
+    }
+
+    #[test]
+    fn pan_and_dolly_move_the_target_and_distance() {
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let mut orbit = OrbitController::around(&camera);
+
+        orbit.process_mouse_button(MouseButton::Middle, ElementState::Pressed);
+        orbit.process_cursor(&camera, 1.0, [0.5, 0.0]);
+        orbit.update(&mut camera);
+        // Half a window to the right moves the target by half the visible
+        // width, which at 53.13° and ten units away is five units
+        assert!((orbit.target[1] - 5.0).abs() < 1e-3, "{:?}", orbit.target);
+        assert_eq!(camera.direction(), [1.0, 0.0, 0.0]);
+
+        orbit.dolly(1.0);
+        assert!(orbit.distance < 10.0);
+        orbit.dolly(-1000.0);
+        orbit.dolly(1.0e6);
+        assert_eq!(orbit.distance, MIN_ORBIT_DISTANCE);
+    }
+
+    #[test]
+    fn mouse_look_follows_sensitivity_and_invert_y() {
+        let look = MouseLook { sensitivity: 0.5, invert_y: false };
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        look.rotate(&mut camera, [180.0, 0.0]);
+        assert!(camera.direction()[1] < -0.999, "180 counts turn 90° to the right");
+
+        look.rotate(&mut camera, [0.0, 20.0]);
+        assert!((camera.pitch().0.to_degrees() + 10.0).abs() < 1e-3, "moving down looks down");
+
+        let inverted = MouseLook { invert_y: true, ..look };
+        inverted.rotate(&mut camera, [0.0, 40.0]);
+        assert!((camera.pitch().0.to_degrees() - 10.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{bail, Context, Result};
+use image::codecs::hdr::HdrEncoder;
+use super::pipeline::aov::Aov;
+use super::pipeline::display::{self, Display};
+
+/// File formats rendered images can be saved in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8 bit sRGB, radiance above one is clipped.
+    Png,
+    /// Linear 32 bit float OpenEXR, nothing is clipped. The AOVs follow
+    /// the image as extra layers.
+    Exr,
+    /// Linear Radiance RGBE, smaller than EXR with a shared exponent per
+    /// pixel.
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Exr => "exr",
+            ImageFormat::Hdr => "hdr",
+        }
+    }
+
+    /// The format matching the extension of `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<ImageFormat> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("exr") => Ok(ImageFormat::Exr),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            _ => bail!("{} does not end in .png, .exr or .hdr", path.display()),
+        }
+    }
+}
+
+/// Mean linear radiance of every pixel, row by row.
+pub struct RadianceImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+    /// Texels of every AOV in the order of `Aov::ALL`, or nothing if they
+    /// were not read back. EXRs store them as extra layers.
+    pub aovs: Vec<Vec<[f32; 4]>>,
+}
+
+impl RadianceImage {
+    /// Writes an 8 bit sRGB PNG, exposed and tone mapped by `display`.
+    /// `text` is stored as UTF-8 text chunks of keyword and value.
+    pub fn save_png(&self, path: impl AsRef<Path>, display: &Display, text: &[(&str, String)]) -> Result<()> {
+        let path = path.as_ref();
+        let pixels: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&rgb| display.apply(rgb).map(|x| (255.0 * display::encode_srgb(x)).round() as u8))
+            .collect();
+
+        let write = || -> Result<()> {
+            let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+            for (keyword, value) in text {
+                encoder.add_itxt_chunk(keyword.to_string(), value.clone())?;
+            }
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&pixels)?;
+            writer.finish()?;
+            Ok(())
+        };
+        write().with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Writes the linear radiance as a 32 bit float OpenEXR, followed by a
+    /// layer for each AOV.
+    pub fn save_exr(&self, path: impl AsRef<Path>) -> Result<()> {
+        use exr::prelude::*;
+
+        let path = path.as_ref();
+        let size = Vec2(self.width as usize, self.height as usize);
+        let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+            Layer::new(size, LayerAttributes::named(name), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_vec(channels)))
+        };
+
+        let radiance = ["R", "G", "B"]
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| AnyChannel::new(name, FlatSamples::F32(self.pixels.iter().map(|p| p[i]).collect())))
+            .collect();
+        let mut layers = vec![layer("radiance", radiance)];
+        for (aov, texels) in Aov::ALL.iter().zip(&self.aovs) {
+            let channels = aov
+                .channels()
+                .iter()
+                .enumerate()
+                .map(|(i, &name)| AnyChannel::new(name, FlatSamples::F32(texels.iter().map(|t| t[i]).collect())))
+                .collect();
+            layers.push(layer(aov.name(), channels));
+        }
+
+        Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), SmallVec::from_vec(layers))
+            .write()
+            .to_file(path)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Writes the linear radiance as a Radiance HDR file.
+    pub fn save_hdr(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let pixels: Vec<_> = self.pixels.iter().copied().map(image::Rgb).collect();
+
+        let file = File::create(path).with_context(|| format!("writing {}", path.display()))?;
+        HdrEncoder::new(BufWriter::new(file))
+            .encode(&pixels, self.width as usize, self.height as usize)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// PNGs get exposed and tone mapped by `display`, the floating point
+    /// formats keep the radiance untouched.
+    pub fn save(&self, path: impl AsRef<Path>, format: ImageFormat, display: &Display) -> Result<()> {
+        match format {
+            ImageFormat::Png => self.save_png(path, display, &[]),
+            ImageFormat::Exr => self.save_exr(path),
+            ImageFormat::Hdr => self.save_hdr(path),
+        }
+    }
+}
+
+/// UTC `[year, month, day, hour, minute, second]` of `time`.
+pub fn date_time(time: SystemTime) -> [u64; 6] {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Howard Hinnant's days to civil date, with years starting in March so
+    // leap days come last
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    [year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn dates_are_converted_to_utc() {
+        let at = |seconds| date_time(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), [1970, 1, 1, 0, 0, 0]);
+        assert_eq!(at(951868799), [2000, 2, 29, 23, 59, 59]);
+        assert_eq!(at(1792337412), [2026, 10, 18, 15, 30, 12]);
+    }
+
+    #[test]
+    fn png_text_chunks_are_kept() {
+        let image = RadianceImage {
+            width: 2,
+            height: 1,
+            pixels: vec![[0.0; 3], [4.0, 1.0, 0.25]],
+            aovs: Vec::new(),
+        };
+        let path = std::env::temp_dir().join(format!("text-{}.png", std::process::id()));
+        image.save_png(&path, &Display::default(), &[("Source", "scenes/café.toml".to_string())]).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(&pixels[..6], &[0, 0, 0, 255, 255, 137]);
+
+        let chunk = &reader.info().utf8_text[0];
+        assert_eq!(chunk.keyword, "Source");
+        assert_eq!(chunk.get_text().unwrap(), "scenes/café.toml");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use wgpu::{
+    Device, Queue, Instance, InstanceDescriptor, RequestAdapterOptions,
+    PowerPreference, DeviceDescriptor, Features, Limits, CommandEncoderDescriptor,
+    TextureFormat,
+};
+use winit::dpi::PhysicalSize;
+use super::export::{ImageFormat, RadianceImage};
+use super::options::RenderOptions;
+use super::pipeline::display::Display;
+use super::pipeline::{Pipeline, camera::Camera, scene::Scene};
+
+// Nothing is presented, the format only has to be a valid render target
+const DISPLAY_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Drives the ray tracer without a window, for offline renders and tests.
+pub struct HeadlessState {
+    device: Device,
+    queue: Queue,
+    pipeline: Pipeline,
+}
+
+impl HeadlessState {
+    pub async fn new(size: PhysicalSize<u32>, scene: &Scene) -> Result<Self> {
+        HeadlessState::with_options(size, scene, &RenderOptions::default()).await
+    }
+
+    pub async fn with_options(size: PhysicalSize<u32>, scene: &Scene, options: &RenderOptions) -> Result<Self> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: options.backend.backends(),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: options.backend.force_fallback_adapter(),
+            })
+            .await
+            .ok_or_else(|| anyhow!("no {:?} graphics adapter found", options.backend))?;
+
+        let (device, queue) = adapter
+            .request_device(
+                &DeviceDescriptor {
+                    features: Features::empty(),
+                    limits: Limits::default(),
+                    label: None,
+                },
+                None, // Trace path
+            )
+            .await?;
+
+        let pipeline = Pipeline::new(&device, &queue, DISPLAY_FORMAT, size, scene, options)?;
+
+        Ok(HeadlessState {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// State for GPU tests. Without a usable adapter the test fails, unless
+    /// `RAY_TRACING_SKIP_GPU_TESTS` is set on machines known to lack one, in
+    /// which case it is skipped with a note.
+    #[cfg(test)]
+    pub(crate) fn for_test(size: PhysicalSize<u32>, scene: &Scene, options: &RenderOptions) -> Option<HeadlessState> {
+        match pollster::block_on(HeadlessState::with_options(size, scene, options)) {
+            Ok(state) => Some(state),
+            Err(e) if std::env::var_os("RAY_TRACING_SKIP_GPU_TESTS").is_some() => {
+                eprintln!("skipping GPU test, RAY_TRACING_SKIP_GPU_TESTS is set: {e}");
+                None
+            }
+            Err(e) => panic!("no adapter to run GPU tests on, set RAY_TRACING_SKIP_GPU_TESTS to skip them: {e}"),
+        }
+    }
+
+    pub fn camera(&mut self) -> &mut Camera {
+        self.pipeline.camera()
+    }
+
+    /// Replaces the camera and starts accumulating a new image.
+    pub fn set_camera(&mut self, camera: Camera) {
+        *self.pipeline.camera() = camera;
+        self.pipeline.reset_accumulation();
+    }
+
+    /// Moves animated objects to where they are `time` seconds into the
+    /// scene and starts accumulating a new image.
+    pub fn set_time(&mut self, time: f32) {
+        self.pipeline.set_time(time);
+    }
+
+    /// Traces `samples` more samples per pixel into the accumulation buffer.
+    pub fn render(&mut self, samples: u32) {
+        for _ in 0..samples {
+            self.pipeline.update(&self.queue);
+
+            let mut encoder = self
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Headless Encoder"),
+                });
+            self.pipeline.trace(&mut encoder);
+            self.queue.submit([encoder.finish()]);
+        }
+    }
+
+    /// Reads back the samples of the last frame with their position in the
+    /// pixel, if they were splatted.
+    #[cfg(test)]
+    pub(crate) fn read_samples(&self) -> Vec<([f32; 3], [f32; 2])> {
+        self.pipeline.read_samples(&self.device, &self.queue)
+    }
+
+    /// Reads back the sRGB encoded pixels the viewer would show, with the
+    /// display settings applied.
+    #[cfg(test)]
+    pub(crate) fn read_display(&self) -> Vec<[u8; 4]> {
+        bytemuck::cast_slice(&self.pipeline.read_display(&self.device, &self.queue, DISPLAY_FORMAT)).to_vec()
+    }
+
+    /// Reads back the mean linear radiance of every pixel, row by row.
+    pub fn read_radiance(&self) -> Vec<[f32; 3]> {
+        self.pipeline.read_radiance(&self.device, &self.queue).pixels
+    }
+
+    /// Reads back the radiance together with the AOVs.
+    pub fn image(&self) -> RadianceImage {
+        RadianceImage {
+            aovs: self.pipeline.read_aovs(&self.device, &self.queue),
+            ..self.pipeline.read_radiance(&self.device, &self.queue)
+        }
+    }
+
+    /// Exposure and tone mapping applied to PNGs.
+    pub fn set_display(&mut self, display: Display) {
+        self.pipeline.set_display(display);
+    }
+
+    /// Writes the current image, see `RadianceImage::save`.
+    pub fn save(&self, path: impl AsRef<Path>, format: ImageFormat) -> Result<()> {
+        self.image().save(path, format, &self.pipeline.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Aov, Material, Medium, Object, Shape};
+
+    #[test]
+    fn exports_keep_radiance_above_one() {
+        let mut scene = Scene::new(Camera::default());
+        scene.background = [4.0, 2.0, 0.5];
+        let Some(mut state) = HeadlessState::for_test(PhysicalSize::new(8, 4), &scene, &RenderOptions::default()) else { return };
+        state.render(1);
+
+        let directory = std::env::temp_dir();
+        let exr = directory.join(format!("export-{}.exr", std::process::id()));
+        let hdr = directory.join(format!("export-{}.hdr", std::process::id()));
+        state.save(&exr, ImageFormat::from_path(&exr).unwrap()).unwrap();
+        state.save(&hdr, ImageFormat::from_path(&hdr).unwrap()).unwrap();
+
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            &exr,
+            |resolution, _| vec![[0.0; 3]; resolution.area()],
+            |pixels: &mut Vec<[f32; 3]>, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[position.y() * 8 + position.x()] = [r, g, b];
+            },
+        )
+        .unwrap();
+        assert!(image.layer_data.channel_data.pixels.iter().all(|&p| p == [4.0, 2.0, 0.5]));
+
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(std::fs::File::open(&hdr).unwrap())).unwrap();
+        let decoded = decoder.read_image_hdr().unwrap();
+        assert_eq!(decoded.len(), 8 * 4);
+        for pixel in decoded {
+            // RGBE shares one exponent, so the dim channel loses precision
+            assert!((pixel[0] - 4.0).abs() < 0.05 && (pixel[1] - 2.0).abs() < 0.05 && (pixel[2] - 0.5).abs() < 0.05, "{:?}", pixel);
+        }
+
+        std::fs::remove_file(exr).unwrap();
+        std::fs::remove_file(hdr).unwrap();
+    }
+
+    #[test]
+    fn aovs_describe_the_first_surface_seen() {
+        let mut scene = Scene::new(Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        let material = Material::diffuse([0.2, 0.4, 0.6]);
+        scene.add(Object::sphere([-5.0, 0.0, 0.0], 1.0, material)).unwrap();
+        scene.add(Object::sphere([5.0, 0.0, 0.0], 1.0, material)).unwrap();
+        let Some(mut state) = HeadlessState::for_test(PhysicalSize::new(9, 9), &scene, &RenderOptions::default()) else { return };
+        state.render(1);
+        let image = state.image();
+        let texel = |aov: Aov, x: usize, y: usize| image.aovs[aov.layer() as usize][y * 9 + x];
+
+        // Both spheres share a material, only the one in front is seen
+        let [depth, ..] = texel(Aov::Depth, 4, 4);
+        assert!((depth - 4.0).abs() < 0.1, "{depth}");
+        let [x, y, z, _] = texel(Aov::Normal, 4, 4);
+        assert!(x < -0.9 && y.abs() < 0.4 && z.abs() < 0.4, "{:?}", [x, y, z]);
+        assert_eq!(texel(Aov::Albedo, 4, 4)[..3], [0.2, 0.4, 0.6]);
+        assert_eq!(texel(Aov::ObjectId, 4, 4)[..2], [1.0, 0.0]);
+        let [x, y, z, _] = texel(Aov::Position, 4, 4);
+        assert!((x - 4.0).abs() < 0.1 && y.abs() < 0.5 && z.abs() < 0.5, "{:?}", [x, y, z]);
+        assert_eq!(texel(Aov::ObjectId, 0, 0)[..2], [-1.0, -1.0]);
+        assert!(texel(Aov::Depth, 0, 0)[0] > 1e30);
+
+        // Further samples leave the AOVs alone
+        state.render(3);
+        assert_eq!(state.image().aovs, image.aovs);
+
+        let path = std::env::temp_dir().join(format!("aovs-{}.exr", std::process::id()));
+        state.save(&path, ImageFormat::Exr).unwrap();
+        let meta = exr::meta::MetaData::read_from_file(&path, false).unwrap();
+        let layers: Vec<String> = meta
+            .headers
+            .iter()
+            .map(|header| header.own_attributes.layer_name.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(layers, ["radiance", "depth", "normal", "albedo", "id", "position"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn first_aovs(scene: &Scene, seed: u32) -> Option<Vec<Vec<[f32; 4]>>> {
+        let options = RenderOptions {
+            seed,
+            ..RenderOptions::default()
+        };
+        let mut state = HeadlessState::for_test(PhysicalSize::new(9, 9), scene, &options)?;
+        state.render(1);
+        Some(state.image().aovs)
+    }
+
+    #[test]
+    fn aovs_look_through_fog_and_volumes() {
+        let mut clear = Scene::new(Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        clear.add(Object::sphere([5.0, 0.0, 0.0], 1.0, Material::diffuse([0.2, 0.4, 0.6]))).unwrap();
+
+        // Dense enough that nearly every path scatters before the sphere
+        let mut foggy = clear.clone();
+        foggy.fog = Some(Medium::fog(0.5));
+        foggy.add(Object::volume(Shape::Cuboid { min: [1.5, -2.0, -2.0], max: [2.5, 2.0, 2.0] }, Medium::smoke(20.0))).unwrap();
+
+        // The seed only moves the first sample around in the pixel
+        for seed in [0, 1] {
+            let Some(expected) = first_aovs(&clear, seed) else { return };
+            let aovs = first_aovs(&foggy, seed).unwrap();
+            assert_eq!(aovs[Aov::ObjectId.layer() as usize], expected[Aov::ObjectId.layer() as usize]);
+            assert_eq!(aovs[Aov::Albedo.layer() as usize], expected[Aov::Albedo.layer() as usize]);
+
+            // Rays leaving the volume start again from its boundary
+            for (layer, (texels, expected)) in aovs.iter().zip(&expected).enumerate() {
+                for (texel, expected) in texels.iter().zip(expected) {
+                    let close = texel.iter().zip(expected).all(|(a, b)| (a - b).abs() <= 1e-3 * b.abs().max(1.0));
+                    assert!(close, "layer {layer}, seed {seed}: {texel:?} against {expected:?}");
+                }
+            }
+        }
+    }
+}
@@ -1,18 +1,52 @@
 mod pipeline;
+mod headless;
+mod controller;
+mod bindings;
+mod options;
+mod export;
+#[cfg(test)]
+mod testing;
 
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
 use wgpu::{
     Surface, Device, SurfaceConfiguration, Queue, SurfaceError, Instance, 
-    InstanceDescriptor, Backends, RequestAdapterOptions, PowerPreference,
+    InstanceDescriptor, RequestAdapterOptions, PowerPreference,
     DeviceDescriptor, Features, Limits, TextureUsages, TextureViewDescriptor,
     CommandEncoderDescriptor,
 };
 use winit::{
     dpi::{PhysicalSize, PhysicalPosition},
-    event::{WindowEvent, VirtualKeyCode, ElementState, KeyboardInput},
-    window::Window,
+    event::{WindowEvent, DeviceEvent, ElementState, KeyboardInput, MouseButton},
+    window::{Window, CursorGrabMode},
 };
 use pipeline::Pipeline;
+use controller::{ButtonUse, FlyController, OrbitController};
+use bindings::Action;
+use pipeline::scene;
 
+pub use headless::HeadlessState;
+pub use export::{ImageFormat, RadianceImage};
+pub use bindings::KeyBindings;
+pub use options::{Backend, RenderOptions, ViewerOptions};
+pub use controller::{MouseLook, Movement};
+pub use pipeline::{
+    camera::{Camera, Fov, Projection, FisheyeMapping, Stereo, StereoLayout, LookMode},
+    material::Material,
+    medium::Medium,
+    grid::DensityGrid,
+    scene::{Scene, Object, Shape},
+    animation::{CameraPath, CameraKeyframe, ObjectAnimation, Transform, TransformKeyframe},
+    display::{Display, ToneMapper},
+    aov::Aov,
+    post::{PostEffects, Bloom, Vignette, Lens, Grain},
+    filter::{Filter, PixelFilter},
+    sampler::Sampler,
+};
+
+// Stops of exposure per key press
+const EXPOSURE_STEP: f32 = 0.5;
 
 pub struct GpuState {
     surface: Surface,
@@ -21,19 +55,43 @@ pub struct GpuState {
     config: SurfaceConfiguration,
     size: PhysicalSize<u32>,
     prev_cursor: Option<PhysicalPosition<f64>>,
+    controller: FlyController,
+    // Replaces the fly controller while present
+    orbit: Option<OrbitController>,
+    mouse_look: MouseLook,
+    bindings: KeyBindings,
+    exit_requested: bool,
+    camera_path: CameraPath,
+    // Where recorded keyframes are saved
+    path_file: Option<PathBuf>,
+    // Seconds into the camera path and the scene's animation while they play
+    playback: Option<f32>,
+    // How long playback lasts
+    duration: f32,
+    // Mouselook only turns the camera while the cursor is grabbed
+    cursor_grabbed: bool,
+    last_update: Instant,
+    // Recorded in screenshots
+    options: ViewerOptions,
     window: Window,
     pipeline: Pipeline,
 }
 
 impl GpuState {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: Window) -> Self {
+    pub async fn new(
+        window: Window,
+        scene: &Scene,
+        bindings: KeyBindings,
+        camera_path: CameraPath,
+        path_file: Option<PathBuf>,
+        options: &ViewerOptions,
+    ) -> Result<Self> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
+            backends: options.render.backend.backends(),
             dx12_shader_compiler: Default::default(),
         });
 
@@ -41,16 +99,16 @@ impl GpuState {
         //
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&window) }?;
 
         let adapter = instance
             .request_adapter(&RequestAdapterOptions {
                 power_preference: PowerPreference::default(),
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter: options.render.backend.force_fallback_adapter(),
             })
             .await
-            .unwrap();
+            .ok_or_else(|| anyhow!("no {:?} graphics adapter can draw to the window", options.render.backend))?;
 
         let (device, queue) = adapter
             .request_device(
@@ -67,42 +125,61 @@ impl GpuState {
                 },
                 None, // Trace path
             )
-            .await
-            .unwrap();
+            .await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
+        // sRGB surfaces are preferred, the display shader encodes by itself
+        // for the others
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
+        let present_mode = match options.present_mode {
+            Some(mode) if surface_caps.present_modes.contains(&mode) => mode,
+            Some(mode) => {
+                log::warn!("{mode:?} presentation is not supported, using {:?}", surface_caps.present_modes[0]);
+                surface_caps.present_modes[0]
+            }
+            None => surface_caps.present_modes[0],
+        };
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
         surface.configure(&device, &config);
 
-        let pipeline = Pipeline::new(&device, &config, size);
+        let mut pipeline = Pipeline::new(&device, &queue, config.format, size, scene, &options.render)?;
+        pipeline.set_sample_limit(options.samples);
 
-        GpuState {
+        Ok(GpuState {
             surface,
             device,
             queue,
             config,
             size,
             prev_cursor: None,
+            controller: FlyController::new(options.movement),
+            orbit: None,
+            mouse_look: options.mouse_look,
+            bindings,
+            exit_requested: false,
+            camera_path,
+            path_file,
+            playback: None,
+            duration: scene.animation_duration(),
+            cursor_grabbed: false,
+            last_update: Instant::now(),
+            options: options.clone(),
             window,
             pipeline,
-        }
+        })
     }
 
     pub fn window(&self) -> &Window {
@@ -126,46 +203,57 @@ impl GpuState {
         match event {
             WindowEvent::KeyboardInput {
                 input: KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(VirtualKeyCode::Up),
+                    state,
+                    virtual_keycode: Some(key),
                     ..
                 },
                 ..
-            } => {
-                self.pipeline.camera().forwards();
-                true
+            } => match self.bindings.action(*key) {
+                Some(action) => {
+                    self.perform(action, *state);
+                    true
+                }
+                None => false,
             },
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(VirtualKeyCode::Down),
-                    ..
-                },
-                ..
-            } => {
-                self.pipeline.camera().backwards();
-                true
+            WindowEvent::Focused(false) => {
+                self.set_cursor_grab(false);
+                self.controller.release_all();
+                if let Some(orbit) = &mut self.orbit {
+                    orbit.release_all();
+                }
+                false
             },
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(VirtualKeyCode::Right),
-                    ..
-                },
+            WindowEvent::MouseInput { state, button, .. } if self.orbit.is_some() => {
+                match self.orbit.as_mut().unwrap().process_mouse_button(*button, *state) {
+                    ButtonUse::Click => {
+                        self.focus_at_cursor();
+                        true
+                    }
+                    ButtonUse::Drag => true,
+                    ButtonUse::Ignored => false,
+                }
+            },
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
                 ..
             } => {
-                self.pipeline.camera().rightwards();
+                self.set_cursor_grab(!self.cursor_grabbed);
                 true
             },
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(VirtualKeyCode::Left),
-                    ..
-                },
+            WindowEvent::MouseWheel { delta, .. } => match &mut self.orbit {
+                Some(orbit) => {
+                    orbit.process_scroll(delta);
+                    true
+                }
+                None => false,
+            },
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
                 ..
             } => {
-                self.pipeline.camera().leftwards();
+                self.focus_at_cursor();
                 true
             },
             WindowEvent::CursorLeft { .. } => {
@@ -179,9 +267,13 @@ impl GpuState {
             WindowEvent::CursorMoved { position, .. } => {
                 if let Some(s) = self.prev_cursor {
                     let pct_x = (position.x - s.x)/(self.size.width as f64);
-                    self.pipeline.camera().rotate_rightwards(pct_x as f32);
                     let pct_y = (position.y - s.y)/(self.size.height as f64);
-                    self.pipeline.camera().rotate_upwards(pct_y as f32);
+                    // Orbiting only follows drags, mouselook uses raw motion
+                    // instead, see `device_input`
+                    if let Some(orbit) = &mut self.orbit {
+                        let aspect = self.size.width as f32 / self.size.height as f32;
+                        orbit.process_cursor(self.pipeline.camera(), aspect, [pct_x as f32, pct_y as f32]);
+                    }
                 }
                 self.prev_cursor = Some(*position);
                 true
@@ -190,8 +282,234 @@ impl GpuState {
         }
     }
 
+    /// Click to focus on whatever is under the cursor, or in the middle of
+    /// the view while mouselook hides it.
+    fn focus_at_cursor(&mut self) {
+        if self.cursor_grabbed {
+            self.pipeline.focus_at([0.5, 0.5]);
+        } else if let Some(cursor) = self.prev_cursor {
+            self.pipeline.focus_at([
+                (cursor.x / self.size.width as f64) as f32,
+                (cursor.y / self.size.height as f64) as f32,
+            ]);
+        }
+    }
+
+    fn perform(&mut self, action: Action, state: ElementState) {
+        if action.is_held() {
+            match state {
+                // The orbit controller ignores the keyboard
+                ElementState::Pressed if self.orbit.is_some() => {}
+                ElementState::Pressed => {
+                    self.controller.press(action);
+                }
+                ElementState::Released => {
+                    self.controller.release(action);
+                }
+            }
+            return;
+        }
+        if state == ElementState::Released {
+            return;
+        }
+
+        match action {
+            Action::CycleProjection => self.pipeline.camera().cycle_projection(),
+            Action::RollLeft => self.pipeline.camera().roll_rightwards(-1.0 / 72.0),
+            Action::RollRight => self.pipeline.camera().roll_rightwards(1.0 / 72.0),
+            Action::ToggleLookMode => self.pipeline.camera().toggle_look_mode(),
+            Action::ToggleController => {
+                self.orbit = match self.orbit {
+                    Some(_) => None,
+                    None => {
+                        self.set_cursor_grab(false);
+                        Some(OrbitController::around(self.pipeline.camera()))
+                    }
+                };
+                self.controller.release_all();
+            }
+            Action::GrabCursor => self.set_cursor_grab(!self.cursor_grabbed),
+            Action::RecordKeyframe => {
+                self.camera_path.record(self.pipeline.camera());
+                self.save_camera_path();
+            }
+            Action::ClearPath => {
+                self.camera_path.clear();
+                self.playback = None;
+                self.save_camera_path();
+            }
+            Action::PlayPath => {
+                self.playback = match self.playback {
+                    None if !self.camera_path.is_empty() || self.duration > 0.0 => Some(0.0),
+                    _ => None,
+                };
+            }
+            Action::ExposureUp | Action::ExposureDown => {
+                let mut display = self.pipeline.display();
+                display.exposure += if action == Action::ExposureUp { EXPOSURE_STEP } else { -EXPOSURE_STEP };
+                self.pipeline.set_display(display);
+            }
+            Action::CycleToneMapper => {
+                let mut display = self.pipeline.display();
+                display.tone_mapper = display.tone_mapper.next();
+                log::info!("tone mapping with {:?}", display.tone_mapper);
+                self.pipeline.set_display(display);
+            }
+            Action::CycleAov => {
+                let aov = Aov::cycle(self.pipeline.shown_aov());
+                log::info!("showing {}", aov.map_or("the image", Aov::name));
+                self.pipeline.set_shown_aov(aov);
+            }
+            Action::ToggleDenoiser => {
+                let denoise = !self.pipeline.denoise();
+                log::info!("denoiser {}", if denoise { "on" } else { "off" });
+                self.pipeline.set_denoise(denoise);
+            }
+            Action::ToggleBloom => self.toggle_post_effect("bloom", |effects| &mut effects.bloom.enabled),
+            Action::ToggleVignette => self.toggle_post_effect("vignette", |effects| &mut effects.vignette.enabled),
+            Action::ToggleLens => self.toggle_post_effect("lens distortion", |effects| &mut effects.lens.enabled),
+            Action::ToggleGrain => self.toggle_post_effect("film grain", |effects| &mut effects.grain.enabled),
+            Action::ToggleTemporal => {
+                let temporal = !self.pipeline.temporal();
+                log::info!("temporal reprojection {}", if temporal { "on" } else { "off" });
+                self.pipeline.set_temporal(temporal);
+            }
+            Action::Screenshot => {
+                if let Err(e) = self.screenshot() {
+                    log::error!("{e:#}");
+                }
+            }
+            // Quitting gives a grabbed cursor back first
+            Action::Quit if self.cursor_grabbed => self.set_cursor_grab(false),
+            Action::Quit => self.exit_requested = true,
+            _ => {}
+        }
+    }
+
+    fn save_camera_path(&self) {
+        if let Some(path_file) = &self.path_file {
+            if let Err(e) = self.camera_path.save(path_file) {
+                log::error!("{e:#}");
+            }
+        }
+    }
+
+    /// Switches the post effect whose `enabled` flag is picked out on or off.
+    fn toggle_post_effect(&mut self, name: &str, enabled: impl FnOnce(&mut PostEffects) -> &mut bool) {
+        let mut effects = self.pipeline.post_effects();
+        let enabled = enabled(&mut effects);
+        *enabled = !*enabled;
+        log::info!("{name} {}", if *enabled { "on" } else { "off" });
+        self.pipeline.set_post_effects(effects);
+    }
+
+    /// Saves the accumulated image as a PNG, with what it takes to render it
+    /// again in its text chunks, and as an EXR keeping the full radiance.
+    fn screenshot(&mut self) -> Result<()> {
+        let now = SystemTime::now();
+        let [year, month, day, hour, minute, second] = export::date_time(now);
+        // Milliseconds keep screenshots taken within a second apart
+        let millis = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_millis());
+        let directory = self.options.screenshot_directory.clone().unwrap_or_default();
+        let stem = directory.join(format!("screenshot-{year}-{month:02}-{day:02}-{hour:02}{minute:02}{second:02}-{millis:03}"));
+
+        let display = self.pipeline.display();
+        let render = &self.options.render;
+        let settings = format!(
+            "samples = {}\ntime = {}\nmax_bounces = {}\nseed = {}\nexposure = {}\ntone_mapper = \"{:?}\"\ndenoise = {}\n",
+            self.pipeline.samples(),
+            self.pipeline.time(),
+            render.max_bounces,
+            render.seed,
+            display.exposure,
+            display.tone_mapper,
+            self.pipeline.denoise(),
+        );
+        let source = match &self.options.scene_file {
+            Some(path) => path.display().to_string(),
+            None => "built-in scene".to_string(),
+        };
+        let text = [
+            ("Software", format!("ray_tracing {}", env!("CARGO_PKG_VERSION"))),
+            ("Creation Time", format!("{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")),
+            ("Source", source),
+            ("Camera", scene::camera_to_toml(self.pipeline.camera())),
+            ("Settings", settings),
+        ];
+
+        let image = RadianceImage {
+            aovs: self.pipeline.read_aovs(&self.device, &self.queue),
+            ..self.pipeline.read_radiance(&self.device, &self.queue)
+        };
+        let png = stem.with_extension("png");
+        image.save_png(&png, &display, &text)?;
+        image.save_exr(stem.with_extension("exr"))?;
+        log::info!("saved {} at {} samples per pixel", png.display(), self.pipeline.samples());
+        Ok(())
+    }
+
+    /// Whether the user asked to close the viewer.
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    /// Handles raw device events, which keep coming while the cursor is
+    /// grabbed. Returns whether the event was used.
+    pub fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } if self.cursor_grabbed && self.orbit.is_none() => {
+                self.mouse_look.rotate(self.pipeline.camera(), [*dx, *dy]);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Hides the cursor and keeps it inside the window, or gives it back.
+    fn set_cursor_grab(&mut self, grab: bool) {
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        let result = if grab {
+            // Not every platform can do both, X11 only confines and macOS
+            // only locks
+            self.window
+                .set_cursor_grab(CursorGrabMode::Confined)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Locked))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+
+        match result {
+            Ok(()) => {
+                self.window.set_cursor_visible(!grab);
+                self.cursor_grabbed = grab;
+            }
+            Err(e) => log::warn!("could not grab the cursor: {e}"),
+        }
+    }
+
     pub fn update(&mut self) {
-        self.pipeline.update_camera(&self.queue);
+        let now = Instant::now();
+        // Long stalls, like dragging the window, should not teleport the
+        // camera
+        let dt = (now - self.last_update).as_secs_f32().min(0.1);
+        self.last_update = now;
+
+        if let Some(time) = self.playback {
+            self.camera_path.apply(time, self.pipeline.camera());
+            self.pipeline.set_time(time);
+            let duration = self.camera_path.duration().max(self.duration);
+            self.playback = Some(time + dt).filter(|&t| t <= duration);
+        } else {
+            match &self.orbit {
+                Some(orbit) => orbit.update(self.pipeline.camera()),
+                None => {
+                    self.controller.update(self.pipeline.camera(), dt);
+                }
+            }
+        }
+        self.pipeline.update(&self.queue);
     }
 
     pub fn render(&mut self) -> Result<(), SurfaceError> {
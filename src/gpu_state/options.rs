@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+use wgpu::{Backends, PresentMode};
+use super::pipeline::display::Display;
+use super::pipeline::filter::PixelFilter;
+use super::pipeline::sampler::Sampler;
+use super::controller::{MouseLook, Movement};
+
+/// Graphics API the tracer runs on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Whichever adapter the platform prefers.
+    #[default]
+    Auto,
+    Vulkan,
+    Gl,
+    /// A CPU implementation such as llvmpipe or WARP, for machines without
+    /// a usable GPU.
+    Software,
+}
+
+impl Backend {
+    pub(crate) fn backends(self) -> Backends {
+        match self {
+            Backend::Auto | Backend::Software => Backends::all(),
+            Backend::Vulkan => Backends::VULKAN,
+            Backend::Gl => Backends::GL,
+        }
+    }
+
+    pub(crate) fn force_fallback_adapter(self) -> bool {
+        self == Backend::Software
+    }
+}
+
+/// Settings of the tracer shared by the viewer and offline renders.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub backend: Backend,
+    /// Surface interactions, scattering events included, after which a path
+    /// is terminated.
+    pub max_bounces: u32,
+    /// Seeds the random numbers, renders with the same seed and sample count
+    /// are identical.
+    pub seed: u32,
+    /// Exposure and tone mapping of the viewer and of PNGs, floating point
+    /// images are always saved untouched.
+    pub display: Display,
+    /// Shows and saves the image filtered by the à-trous denoiser, which
+    /// smooths out noise while few samples have been taken.
+    pub denoise: bool,
+    /// Reprojects the accumulated image whenever the camera or the scene
+    /// time changes, so moving does not drop back to a single noisy sample.
+    /// Off for offline renders, where the history would bias the image.
+    pub temporal: bool,
+    /// Reconstruction filter turning samples into pixels.
+    pub pixel_filter: PixelFilter,
+    /// Sequence the random numbers of every path are drawn from.
+    pub sampler: Sampler,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            backend: Backend::Auto,
+            max_bounces: 8,
+            seed: 0,
+            display: Display::default(),
+            denoise: false,
+            temporal: false,
+            pixel_filter: PixelFilter::default(),
+            sampler: Sampler::default(),
+        }
+    }
+}
+
+/// Settings of the interactive viewer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ViewerOptions {
+    pub render: RenderOptions,
+    /// Inner size of the window, the platform's default if `None`.
+    pub size: Option<[u32; 2]>,
+    /// Samples per pixel after which accumulation stops until the view
+    /// changes, no limit if `None`.
+    pub samples: Option<u32>,
+    /// The surface's preferred mode if `None`.
+    pub present_mode: Option<PresentMode>,
+    /// Where the scene was loaded from, recorded in screenshots so they can
+    /// be reproduced.
+    pub scene_file: Option<PathBuf>,
+    /// Where screenshots are saved, the working directory if `None`.
+    pub screenshot_directory: Option<PathBuf>,
+    /// How the grabbed mouse turns the camera.
+    pub mouse_look: MouseLook,
+    /// How the camera flies around in first person.
+    pub movement: Movement,
+}
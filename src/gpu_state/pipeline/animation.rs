@@ -0,0 +1,495 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+use super::camera::{Camera, Fov};
+
+/// Time between keyframes recorded from the viewer, in seconds.
+pub const KEYFRAME_SPACING: f32 = 2.0;
+
+/// A view of the camera at one point in time.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Orientation quaternion as `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub fov: Fov,
+}
+
+impl CameraKeyframe {
+    pub fn from_camera(time: f32, camera: &Camera) -> CameraKeyframe {
+        let rotation = camera.orientation();
+        CameraKeyframe {
+            time,
+            position: camera.position(),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            fov: camera.fov(),
+        }
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        let [x, y, z, w] = self.rotation;
+        Quaternion::new(w, x, y, z)
+    }
+
+    /// Moves `camera` to this view, leaving its lens and projection alone.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_position(self.position);
+        camera.set_orientation(self.orientation());
+        camera.set_fov(self.fov);
+    }
+}
+
+/// Camera fly-through made of keyframes. Positions and field of view follow
+/// Catmull-Rom splines through the keyframes, orientations are slerped.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    #[serde(default)]
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath::default()
+    }
+
+    /// Where the path of the scene at `scene` is stored: next to it, with
+    /// `.path.toml` in place of the extension.
+    pub fn path_for_scene(scene: impl AsRef<Path>) -> PathBuf {
+        scene.as_ref().with_extension("path.toml")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<CameraPath> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut camera_path: CameraPath = toml::from_str(&text).with_context(|| format!("loading {}", path.display()))?;
+        for keyframe in &camera_path.keyframes {
+            keyframe.fov.validate().with_context(|| format!("keyframe at {} s in {}", keyframe.time, path.display()))?;
+        }
+        camera_path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = toml::to_string_pretty(self)?;
+        std::fs::write(path, text).with_context(|| format!("writing {}", path.display()))
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// Adds a keyframe, keeping them ordered by time.
+    pub fn insert(&mut self, keyframe: CameraKeyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    /// Appends the current view of `camera`, `KEYFRAME_SPACING` seconds after
+    /// the last keyframe.
+    pub fn record(&mut self, camera: &Camera) {
+        let time = self.keyframes.last().map_or(0.0, |k| k.time + KEYFRAME_SPACING);
+        self.insert(CameraKeyframe::from_camera(time, camera));
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Number of frames needed to show the whole path at `fps`, including
+    /// both ends.
+    pub fn frame_count(&self, fps: f32) -> u32 {
+        (self.duration() * fps).floor() as u32 + 1
+    }
+
+    /// The interpolated view at `time`, clamped to the ends of the path.
+    /// `None` for an empty path.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let keys = &self.keyframes;
+        let (i, s) = segment(keys.iter().map(|k| k.time), time)?;
+        let j = (i + 1).min(keys.len() - 1);
+        let [k0, k1, k2, k3] = [i.saturating_sub(1), i, j, (j + 1).min(keys.len() - 1)].map(|n| &keys[n]);
+        let times = [k0.time, k1.time, k2.time, k3.time];
+
+        let position = catmull_rom(times, [k0.position, k1.position, k2.position, k3.position], s);
+        let rotation = k1.orientation().slerp(k2.orientation(), s);
+
+        // The angle is only interpolated between keyframes measuring it along
+        // the same axis
+        let fov = match (k1.fov, k2.fov) {
+            (Fov::Vertical(_), Fov::Vertical(_)) | (Fov::Horizontal(_), Fov::Horizontal(_)) => {
+                let angle = |fov: Fov| match fov {
+                    Fov::Vertical(a) | Fov::Horizontal(a) => [a],
+                };
+                let [a] = catmull_rom(times, [angle(k0.fov), angle(k1.fov), angle(k2.fov), angle(k3.fov)], s);
+                match k1.fov {
+                    Fov::Vertical(_) => Fov::Vertical(a),
+                    Fov::Horizontal(_) => Fov::Horizontal(a),
+                }
+            }
+            _ => k1.fov,
+        };
+
+        Some(CameraKeyframe {
+            time,
+            position,
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            fov,
+        })
+    }
+
+    /// Moves `camera` to the view at `time`, does nothing for an empty path.
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        if let Some(keyframe) = self.sample(time) {
+            keyframe.apply(camera);
+        }
+    }
+}
+
+/// Placement of an object relative to where the scene puts it. Rotation and
+/// scale act about the centre of the object's shape.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    /// Rotation quaternion as `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    /// Must not be zero, `Scene::add` rejects objects scaled to nothing.
+    pub scale: [f32; 3],
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: [0.0; 3],
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        scale: [1.0; 3],
+    };
+
+    pub fn with_translation(self, translation: [f32; 3]) -> Transform {
+        Transform {
+            translation,
+            ..self
+        }
+    }
+
+    /// Replaces the rotation with one of `degrees` about `axis`.
+    pub fn with_rotation(self, axis: [f32; 3], degrees: f32) -> Transform {
+        let rotation = Quaternion::from_axis_angle(Vector3::from(axis).normalize(), Deg(degrees));
+        Transform {
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            ..self
+        }
+    }
+
+    pub fn with_scale(self, scale: [f32; 3]) -> Transform {
+        Transform {
+            scale,
+            ..self
+        }
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        let [x, y, z, w] = self.rotation;
+        Quaternion::new(w, x, y, z)
+    }
+
+    /// Moves a point of the transformed object about `center` back to where
+    /// it is in the untransformed shape.
+    pub fn point_to_rest(&self, center: [f32; 3], point: [f32; 3]) -> [f32; 3] {
+        let offset = [0, 1, 2].map(|i| point[i] - center[i] - self.translation[i]);
+        let local = self.direction_to_rest(offset);
+        [0, 1, 2].map(|i| center[i] + local[i])
+    }
+
+    /// Direction in the untransformed shape. It is not normalised, so
+    /// distances along a ray are the same before and after.
+    pub fn direction_to_rest(&self, direction: [f32; 3]) -> [f32; 3] {
+        let local: [f32; 3] = self.orientation().invert().rotate_vector(direction.into()).into();
+        [0, 1, 2].map(|i| local[i] / self.scale[i])
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::IDENTITY
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransformKeyframe {
+    /// Seconds from the start of the scene.
+    pub time: f32,
+    #[serde(flatten)]
+    pub transform: Transform,
+}
+
+impl TransformKeyframe {
+    pub fn new(time: f32, transform: Transform) -> TransformKeyframe {
+        TransformKeyframe { time, transform }
+    }
+
+    pub fn into_storage(self) -> TransformKeyStorage {
+        let Transform { translation, rotation, scale } = self.transform;
+        TransformKeyStorage {
+            translation,
+            time: self.time,
+            rotation,
+            scale,
+
+            _padding: 0,
+        }
+    }
+}
+
+/// Keyframed transform of a scene object. Translation and scale are
+/// interpolated linearly and rotation is slerped, the same way the tracer
+/// does it, so objects move at constant speed between keyframes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectAnimation {
+    #[serde(default)]
+    keyframes: Vec<TransformKeyframe>,
+}
+
+impl ObjectAnimation {
+    pub fn new() -> ObjectAnimation {
+        ObjectAnimation::default()
+    }
+
+    pub fn with_keyframe(mut self, time: f32, transform: Transform) -> ObjectAnimation {
+        self.insert(TransformKeyframe::new(time, transform));
+        self
+    }
+
+    pub fn keyframes(&self) -> &[TransformKeyframe] {
+        &self.keyframes
+    }
+
+    /// Adds a keyframe, keeping them ordered by time.
+    pub fn insert(&mut self, keyframe: TransformKeyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// The transform at `time`, clamped to the ends of the animation. Empty
+    /// animations leave the object where it is.
+    pub fn sample(&self, time: f32) -> Transform {
+        let keys = &self.keyframes;
+        let Some((i, s)) = segment(keys.iter().map(|k| k.time), time) else {
+            return Transform::IDENTITY;
+        };
+        let start = keys[i].transform;
+        let end = keys[(i + 1).min(keys.len() - 1)].transform;
+
+        let lerp = |a: [f32; 3], b: [f32; 3]| [0, 1, 2].map(|k| a[k] + s * (b[k] - a[k]));
+        let rotation = start.orientation().slerp(end.orientation(), s).normalize();
+        Transform {
+            translation: lerp(start.translation, end.translation),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: lerp(start.scale, end.scale),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TransformKeyStorage {
+    translation: [f32; 3],
+    time: f32,
+    rotation: [f32; 4],
+    scale: [f32; 3],
+    _padding: u32,
+}
+
+/// Index of the keyframe starting the segment that contains `time`, and how
+/// far into the segment it lies. Times outside the keyframes are clamped.
+pub(crate) fn segment(times: impl ExactSizeIterator<Item = f32> + Clone, time: f32) -> Option<(usize, f32)> {
+    let count = times.len();
+    let first = times.clone().next()?;
+    if count == 1 || time <= first {
+        return Some((0, 0.0));
+    }
+
+    let i = times.clone().skip(1).position(|t| t > time).unwrap_or(count - 2);
+    let start = times.clone().nth(i).unwrap();
+    let end = times.clone().nth(i + 1).unwrap();
+    let s = if end > start { ((time - start) / (end - start)).clamp(0.0, 1.0) } else { 1.0 };
+    Some((i, s))
+}
+
+/// Catmull-Rom spline between `points[1]` and `points[2]`, `s` in `[0, 1]`.
+/// The tangents are scaled by the keyframe times, so unevenly spaced
+/// keyframes keep a steady speed. Repeating an end point gives a one-sided
+/// tangent there.
+pub(crate) fn catmull_rom<const N: usize>(times: [f32; 4], points: [[f32; N]; 4], s: f32) -> [f32; N] {
+    let [t0, t1, t2, t3] = times;
+    let [p0, p1, p2, p3] = points;
+    let h = t2 - t1;
+
+    let tangent = |before: [f32; N], after: [f32; N], dt: f32, k: usize| {
+        if dt > 0.0 { (after[k] - before[k]) / dt } else { 0.0 }
+    };
+
+    // Cubic Hermite basis
+    let s2 = s * s;
+    let s3 = s2 * s;
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+
+    std::array::from_fn(|k| {
+        let m1 = tangent(p0, p2, t2 - t0, k);
+        let m2 = tangent(p1, p3, t3 - t1, k);
+        h00 * p1[k] + h10 * h * m1 + h01 * p2[k] + h11 * h * m2
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::PhysicalSize;
+    use crate::{HeadlessState, Material, Object, Projection, RenderOptions, Scene};
+
+    fn path(positions: &[[f32; 3]]) -> CameraPath {
+        let mut path = CameraPath::new();
+        for (i, &position) in positions.iter().enumerate() {
+            let camera = Camera::new(position, [1.0, 0.0, 0.0]);
+            path.insert(CameraKeyframe::from_camera(i as f32, &camera));
+        }
+        path
+    }
+
+    #[test]
+    fn path_passes_through_its_keyframes() {
+        let path = path(&[[0.0, 0.0, 0.0], [1.0, 2.0, 0.0], [3.0, 2.0, 1.0], [4.0, 0.0, 0.0]]);
+        for keyframe in path.keyframes() {
+            let sampled = path.sample(keyframe.time).unwrap();
+            for (a, b) in sampled.position.iter().zip(keyframe.position) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+        assert_eq!(path.sample(-1.0).unwrap().position, [0.0, 0.0, 0.0]);
+        assert_eq!(path.sample(10.0).unwrap().position, [4.0, 0.0, 0.0]);
+        assert!(CameraPath::new().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn evenly_spaced_points_on_a_line_move_at_constant_speed() {
+        let path = path(&[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [3.0, 0.0, 0.0]]);
+        for time in [0.25, 1.5, 2.75] {
+            let position = path.sample(time).unwrap().position;
+            assert!((position[0] - time).abs() < 1e-5, "{time}: {:?}", position);
+        }
+    }
+
+    #[test]
+    fn orientation_is_slerped() {
+        let mut path = CameraPath::new();
+        path.insert(CameraKeyframe::from_camera(0.0, &Camera::new([0.0; 3], [1.0, 0.0, 0.0])));
+        path.insert(CameraKeyframe::from_camera(1.0, &Camera::new([0.0; 3], [0.0, 1.0, 0.0])));
+
+        let mut camera = Camera::default();
+        path.apply(0.5, &mut camera);
+        let [x, y, z] = camera.direction();
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((x - half).abs() < 1e-5 && (y - half).abs() < 1e-5 && z.abs() < 1e-5);
+    }
+
+    #[test]
+    fn paths_round_trip_through_toml() {
+        let mut path = path(&[[0.0, 1.0, 2.0], [3.0, 4.0, 5.0]]);
+        let mut camera = Camera::default().with_fov(Fov::Vertical(40.0));
+        camera.roll_rightwards(0.1);
+        path.record(&camera);
+        assert_eq!(path.duration(), 1.0 + KEYFRAME_SPACING);
+
+        let file = std::env::temp_dir().join(format!("camera-path-{}.path.toml", std::process::id()));
+        path.save(&file).unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(loaded, path);
+
+        assert_eq!(CameraPath::path_for_scene("scenes/box.toml"), Path::new("scenes/box.path.toml"));
+    }
+
+    #[test]
+    fn object_transforms_are_interpolated_between_keyframes() {
+        let animation = ObjectAnimation::new()
+            .with_keyframe(2.0, Transform::IDENTITY.with_translation([4.0, 0.0, 0.0]).with_rotation([0.0, 0.0, 1.0], 90.0))
+            .with_keyframe(0.0, Transform::IDENTITY.with_scale([3.0, 1.0, 1.0]));
+        assert_eq!(animation.duration(), 2.0);
+        assert_eq!(animation.sample(-1.0), Transform::IDENTITY.with_scale([3.0, 1.0, 1.0]));
+        assert_eq!(ObjectAnimation::new().sample(1.0), Transform::IDENTITY);
+
+        let halfway = animation.sample(1.0);
+        assert_eq!(halfway.translation, [2.0, 0.0, 0.0]);
+        assert_eq!(halfway.scale, [2.0, 1.0, 1.0]);
+        let expected = Transform::IDENTITY.with_rotation([0.0, 0.0, 1.0], 45.0).rotation;
+        for (a, b) in halfway.rotation.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-5, "{:?}", halfway.rotation);
+        }
+
+        // Turned a quarter to the left and stretched along y, which was x
+        // before the turn
+        let transform = Transform::IDENTITY.with_rotation([0.0, 0.0, 1.0], 90.0).with_scale([2.0, 1.0, 1.0]);
+        let rest = transform.point_to_rest([1.0, 1.0, 0.0], [1.0, 3.0, 0.0]);
+        for (a, b) in rest.iter().zip([2.0, 1.0, 0.0]) {
+            assert!((a - b).abs() < 1e-5, "{:?}", rest);
+        }
+    }
+
+    /// Brightness of the pixel `column` of a 32 by 8 orthographic view of a
+    /// black ball in front of a white background. The ball moves two units
+    /// to the right, eight pixels, during the first second.
+    fn render_moving_ball(shutter: f32, time: f32, columns: &[usize]) -> Option<Vec<f32>> {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0])
+            .with_projection(Projection::Orthographic { view_height: 2.0 })
+            .with_shutter(0.0, shutter);
+        let mut scene = Scene::new(camera);
+        let animation = scene.add_animation(
+            ObjectAnimation::new()
+                .with_keyframe(0.0, Transform::IDENTITY)
+                .with_keyframe(1.0, Transform::IDENTITY.with_translation([0.0, -2.0, 0.0])),
+        );
+        scene.add(Object::sphere([5.0, 0.0, 0.0], 0.5, Material::diffuse([0.0, 0.0, 0.0])).with_animation(animation)).unwrap();
+
+        let mut state = HeadlessState::for_test(PhysicalSize::new(32, 8), &scene, &RenderOptions::default())?;
+        state.set_time(time);
+        state.render(256);
+        let radiance = state.read_radiance();
+        Some(columns.iter().map(|&x| radiance[3 * 32 + x][0]).collect())
+    }
+
+    #[test]
+    fn moving_objects_blur_while_the_shutter_is_open() {
+        let Some(still) = render_moving_ball(0.0, 0.0, &[16, 20]) else { return };
+        assert!(still[0] < 0.05 && still[1] > 0.95, "{:?}", still);
+
+        let Some(later) = render_moving_ball(0.0, 1.0, &[16, 24]) else { return };
+        assert!(later[0] > 0.95 && later[1] < 0.05, "{:?}", later);
+
+        // The middle pixel is covered for the first third of the exposure and
+        // the one a unit further for about half of it
+        let Some(blurred) = render_moving_ball(1.0, 0.0, &[16, 20]) else { return };
+        assert!((0.55..0.85).contains(&blurred[0]), "{:?}", blurred);
+        assert!((0.35..0.65).contains(&blurred[1]), "{:?}", blurred);
+    }
+}
@@ -0,0 +1,82 @@
+use wgpu::TextureFormat;
+
+pub const AOV_DEPTH: u32 = 0;
+pub const AOV_NORMAL: u32 = 1;
+pub const AOV_ALBEDO: u32 = 2;
+pub const AOV_ID: u32 = 3;
+pub const AOV_POSITION: u32 = 4;
+
+/// Layers of the AOV texture array, one per output variable.
+pub const AOV_COUNT: u32 = 5;
+
+/// Ids and positions need full floats, half floats would round them.
+pub const AOV_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+pub const AOV_TEXEL_SIZE: u32 = 16;
+
+/// Arbitrary output variables, what the camera sees first in each pixel
+/// besides its colour. They are taken from the first sample after the view
+/// changed, so unlike the radiance they are not antialiased, and describe
+/// the first surface, looking through fog and volumes. Pixels seeing
+/// nothing have a depth of `3.4e38`, the background as albedo and ids of
+/// `-1`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance along the view direction, straight line distance for
+    /// panoramas.
+    Depth,
+    /// World space normal facing the camera.
+    Normal,
+    /// Base colour of the material.
+    Albedo,
+    /// Index of the object and of its material among the scene's distinct
+    /// materials.
+    ObjectId,
+    /// World space position.
+    Position,
+}
+
+impl Aov {
+    pub const ALL: [Aov; AOV_COUNT as usize] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::Position];
+
+    /// Layer of the AOV texture array holding this variable.
+    pub fn layer(self) -> u32 {
+        match self {
+            Aov::Depth => AOV_DEPTH,
+            Aov::Normal => AOV_NORMAL,
+            Aov::Albedo => AOV_ALBEDO,
+            Aov::ObjectId => AOV_ID,
+            Aov::Position => AOV_POSITION,
+        }
+    }
+
+    /// Layer name in EXRs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "id",
+            Aov::Position => "position",
+        }
+    }
+
+    /// Names of the channels in use, the rest of the texel is padding.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId => &["object", "material"],
+        }
+    }
+
+    /// Steps through the AOVs shown in the viewer, `None` being the image
+    /// itself.
+    pub fn cycle(shown: Option<Aov>) -> Option<Aov> {
+        match shown {
+            None => Some(Aov::ALL[0]),
+            Some(aov) => Aov::ALL.get(aov.layer() as usize + 1).copied(),
+        }
+    }
+}
@@ -1,78 +1,686 @@
+use anyhow::{ensure, Result};
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Vector3, InnerSpace, Rad, Rotation3, Rotation, Angle, Basis3};
+use serde::{Deserialize, Serialize};
+use cgmath::{Vector3, Quaternion, InnerSpace, Rad, Rotation3, Rotation, Angle, Deg};
+
+pub const PROJECTION_PERSPECTIVE: u32 = 0;
+pub const PROJECTION_ORTHOGRAPHIC: u32 = 1;
+pub const PROJECTION_EQUIRECTANGULAR: u32 = 2;
+pub const PROJECTION_FISHEYE_EQUIDISTANT: u32 = 3;
+pub const PROJECTION_FISHEYE_EQUISOLID: u32 = 4;
+pub const PROJECTION_CUBE_MAP: u32 = 5;
+
+pub const STEREO_NONE: u32 = 0;
+pub const STEREO_SIDE_BY_SIDE: u32 = 1;
+pub const STEREO_OVER_UNDER: u32 = 2;
+
+/// Field of view in degrees, measured along one axis of the image. The other
+/// axis follows from the aspect ratio.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fov {
+    Vertical(f32),
+    Horizontal(f32),
+}
+
+impl Fov {
+    /// Fails unless the angle is between 0° and 180°, which is all a
+    /// perspective image can show.
+    pub fn validate(&self) -> Result<()> {
+        let (Fov::Vertical(degrees) | Fov::Horizontal(degrees)) = *self;
+        ensure!(degrees > 0.0 && degrees < 180.0, "field of view has to be between 0° and 180°, not {}°", degrees);
+        Ok(())
+    }
+}
+
+/// Written as `{ type = "fisheye", mapping = "equisolid", fov = 180.0 }` in
+/// scene files.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays, `view_height` is the height of the visible area in
+    /// scene units.
+    Orthographic { view_height: f32 },
+    /// Full 360° by 180° panorama, longitude along x and latitude along y,
+    /// with the view direction in the middle.
+    Equirectangular,
+    /// Circular image covering `fov` degrees across its diameter.
+    Fisheye { mapping: FisheyeMapping, fov: f32 },
+    /// Six 90° faces in a 3 by 2 grid: right, left and up on the top row,
+    /// down, front and back on the bottom row.
+    CubeMap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FisheyeMapping {
+    /// Distance from the centre proportional to the angle.
+    Equidistant,
+    /// Equal areas on the image cover equal solid angles.
+    Equisolid,
+}
+
+impl Projection {
+    /// Width over height of the images this projection produces, `None` if
+    /// it adapts to any aspect ratio.
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        match self {
+            Projection::Perspective | Projection::Orthographic { .. } => None,
+            Projection::Equirectangular => Some(2.0),
+            Projection::Fisheye { .. } => Some(1.0),
+            Projection::CubeMap => Some(1.5),
+        }
+    }
+
+    /// Fails unless orthographic views have a positive height and fisheyes
+    /// cover more than nothing and at most the full circle.
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Projection::Orthographic { view_height } => {
+                ensure!(view_height > 0.0, "orthographic view height has to be positive, not {}", view_height);
+            }
+            Projection::Fisheye { fov, .. } => {
+                ensure!(fov > 0.0 && fov <= 360.0, "fisheye field of view has to be between 0° and 360°, not {}°", fov);
+            }
+            Projection::Perspective | Projection::Equirectangular | Projection::CubeMap => {}
+        }
+        Ok(())
+    }
+}
+
+/// How the two eyes of a stereo image share the frame. The left eye is on
+/// the left or on top.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoLayout {
+    SideBySide,
+    OverUnder,
+}
+
+/// Renders a separate image for each eye. Equirectangular panoramas use
+/// omni-directional stereo, where the eyes circle the camera position so the
+/// panorama looks right in every direction.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stereo {
+    /// Distance between the eyes in scene units.
+    pub interpupillary_distance: f32,
+    /// Distance at which both eyes see the same point, objects there appear
+    /// on the screen plane. Infinity keeps the eyes parallel.
+    #[serde(default = "parallel")]
+    pub convergence: f32,
+    pub layout: StereoLayout,
+}
+
+impl Stereo {
+    pub fn new(interpupillary_distance: f32, convergence: f32, layout: StereoLayout) -> Result<Stereo> {
+        let stereo = Stereo {
+            interpupillary_distance,
+            convergence,
+            layout,
+        };
+        stereo.validate()?;
+        Ok(stereo)
+    }
+
+    /// Fails unless the eyes converge at a positive distance, which `new`
+    /// checks for but setting the fields directly does not.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.convergence > 0.0, "stereo convergence has to be positive, not {}", self.convergence);
+        Ok(())
+    }
+
+    /// Scales `[width, height]` of one eye to the size of the whole frame.
+    fn frame_size(&self, [width, height]: [u32; 2]) -> [u32; 2] {
+        match self.layout {
+            StereoLayout::SideBySide => [2 * width, height],
+            StereoLayout::OverUnder => [width, 2 * height],
+        }
+    }
+}
+
+fn parallel() -> f32 {
+    f32::INFINITY
+}
+
+/// Pitch is kept this far from straight up or down in first person mode.
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+
+/// How mouse look turns the camera.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LookMode {
+    /// Turns around the world's vertical axis and stops short of looking
+    /// straight up or down.
+    FirstPerson,
+    /// Turns around the camera's own axes without limits, like a spaceship.
+    Free,
+}
 
 #[derive(Copy, Clone, Debug)]
 pub struct Camera {
     position: Vector3<f32>,
-    forwards: Vector3<f32>,
+    // Rotates the reference frame looking along x with z up into the view
+    orientation: Quaternion<f32>,
+    look_mode: LookMode,
+    fov: Fov,
+    projection: Projection,
+    // Thin lens, an aperture of zero is a pinhole
+    aperture: f32,
+    focus_distance: f32,
+    blades: u32,
+    stereo: Option<Stereo>,
+    // Seconds relative to the frame time during which the shutter is open
+    shutter: [f32; 2],
 }
 
 impl Camera {
+    /// Places a level camera looking along `forwards`. The forwards vector
+    /// does not need to be normalised.
     pub fn new(position: [f32; 3], forwards: [f32; 3]) -> Camera {
-        Camera {
+        let mut camera = Camera {
             position: position.into(),
+            orientation: level_orientation(forwards.into()),
+            look_mode: LookMode::FirstPerson,
+            fov: Fov::Horizontal(53.13),
+            projection: Projection::Perspective,
+            aperture: 0.0,
+            focus_distance: 10.0,
+            blades: 0,
+            stereo: None,
+            shutter: [0.0, 0.0],
+        };
+        camera.clamp_pitch();
+        camera
+    }
+
+    /// Gives the camera a lens of radius `aperture` focused on the plane
+    /// `focus_distance` in front of it. With three or more `blades` the
+    /// aperture is a polygon instead of a disk, which shapes the bokeh.
+    pub fn with_lens(self, aperture: f32, focus_distance: f32, blades: u32) -> Camera {
+        Camera {
+            aperture,
+            focus_distance,
+            blades,
+            ..self
+        }
+    }
+
+    pub fn with_fov(self, fov: Fov) -> Camera {
+        Camera {
+            fov,
+            ..self
+        }
+    }
+
+    pub fn fov(&self) -> Fov {
+        self.fov
+    }
+
+    pub fn set_fov(&mut self, fov: Fov) {
+        self.fov = fov;
+    }
+
+    pub fn with_projection(self, projection: Projection) -> Camera {
+        Camera {
+            projection,
+            ..self
+        }
+    }
+
+    pub fn with_stereo(self, stereo: Stereo) -> Camera {
+        Camera {
+            stereo: Some(stereo),
+            ..self
+        }
+    }
+
+    /// Keeps the shutter open from `open` to `close` seconds after the frame
+    /// time, so objects moving meanwhile are blurred. Both zero by default.
+    pub fn with_shutter(self, open: f32, close: f32) -> Camera {
+        Camera {
+            shutter: [open, close],
+            ..self
+        }
+    }
+
+    pub fn shutter(&self) -> [f32; 2] {
+        self.shutter
+    }
+
+    pub fn with_look_mode(mut self, look_mode: LookMode) -> Camera {
+        self.set_look_mode(look_mode);
+        self
+    }
+
+    pub fn look_mode(&self) -> LookMode {
+        self.look_mode
+    }
+
+    /// Going back to first person keeps the current roll but tilts the view
+    /// away from the poles.
+    pub fn set_look_mode(&mut self, look_mode: LookMode) {
+        self.look_mode = look_mode;
+        self.clamp_pitch();
+    }
+
+    pub fn toggle_look_mode(&mut self) {
+        self.set_look_mode(match self.look_mode {
+            LookMode::FirstPerson => LookMode::Free,
+            LookMode::Free => LookMode::FirstPerson,
+        });
+    }
+
+    pub fn stereo(&self) -> Option<Stereo> {
+        self.stereo
+    }
+
+    pub fn set_stereo(&mut self, stereo: Option<Stereo>) {
+        self.stereo = stereo;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Steps through all projections. Going orthographic keeps the focal
+    /// plane the same size on screen.
+    pub fn cycle_projection(&mut self) {
+        self.projection = match self.projection {
+            Projection::Perspective => {
+                let [_, half_height] = self.perspective_half_extent(1.0);
+                Projection::Orthographic { view_height: 2.0 * half_height * self.focus_distance }
+            }
+            Projection::Orthographic { .. } => Projection::Equirectangular,
+            Projection::Equirectangular => Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 },
+            Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov } => {
+                Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov }
+            }
+            Projection::Fisheye { .. } => Projection::CubeMap,
+            Projection::CubeMap => Projection::Perspective,
+        };
+    }
+
+    /// Size of the image to render at `width`, `height` is only used by
+    /// projections without a fixed aspect ratio. For stereo the fixed aspect
+    /// ratio applies to each eye, and the frame holds both.
+    pub fn image_size(&self, width: u32, height: u32) -> [u32; 2] {
+        let Some(aspect) = self.projection.aspect_ratio() else { return [width, height] };
+        let eye_width = match self.stereo {
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) => (width / 2).max(1),
+            _ => width,
+        };
+        let eye = [eye_width, ((eye_width as f32 / aspect).round() as u32).max(1)];
+        match self.stereo {
+            Some(stereo) => stereo.frame_size(eye),
+            None => eye,
+        }
+    }
+
+    /// Aspect ratio of the image seen by one eye.
+    fn eye_aspect(&self, aspect: f32) -> f32 {
+        match self.stereo {
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) => 0.5 * aspect,
+            Some(Stereo { layout: StereoLayout::OverUnder, .. }) => 2.0 * aspect,
+            None => aspect,
+        }
+    }
+
+    /// `aspect` is the width of the image divided by its height.
+    pub fn into_uniform(self, aspect: f32) -> CameraUniform {
+        let (forwards, right, up) = self.basis();
+        let aspect = self.eye_aspect(aspect);
+
+        let (projection, half_extent) = match self.projection {
+            Projection::Perspective => (PROJECTION_PERSPECTIVE, self.perspective_half_extent(aspect)),
+            Projection::Orthographic { view_height } => (
+                PROJECTION_ORTHOGRAPHIC,
+                [0.5 * view_height * aspect, 0.5 * view_height],
+            ),
+            Projection::Equirectangular => (PROJECTION_EQUIRECTANGULAR, [0.0; 2]),
+            // Half the field of view in radians
+            Projection::Fisheye { mapping, fov } => (
+                match mapping {
+                    FisheyeMapping::Equidistant => PROJECTION_FISHEYE_EQUIDISTANT,
+                    FisheyeMapping::Equisolid => PROJECTION_FISHEYE_EQUISOLID,
+                },
+                [0.5 * fov.to_radians(); 2],
+            ),
+            Projection::CubeMap => (PROJECTION_CUBE_MAP, [0.0; 2]),
+        };
+
+        let (stereo, eye_offset, eye_convergence) = match self.stereo {
+            Some(stereo) => (
+                match stereo.layout {
+                    StereoLayout::SideBySide => STEREO_SIDE_BY_SIDE,
+                    StereoLayout::OverUnder => STEREO_OVER_UNDER,
+                },
+                0.5 * stereo.interpupillary_distance,
+                // Tangent of the angle each eye turns inwards, per unit of
+                // eye offset
+                1.0 / stereo.convergence,
+            ),
+            None => (STEREO_NONE, 0.0, 0.0),
+        };
+
+        CameraUniform {
+            position: self.position.into(),
+            aperture: self.aperture,
             forwards: forwards.into(),
+            focus_distance: self.focus_distance,
+            right: right.into(),
+            blades: self.blades,
+            up: up.into(),
+            projection,
+            half_extent,
+            eye_offset,
+            eye_convergence,
+            stereo,
+            shutter: self.shutter,
+
+            _padding: 0,
         }
     }
 
-    pub fn into_uniform(&self) -> CameraUniform {
-        let true_up = Vector3::unit_z();
-        let right = self.forwards.cross(true_up).normalize();
-        let up = right.cross(self.forwards).normalize();
+    pub fn position(&self) -> [f32; 3] {
+        self.position.into()
+    }
+
+    pub fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position.into();
+    }
 
-        CameraUniform::new(
-            self.position.into(),
-            self.forwards.into(),
-            right.into(),
-            up.into(),
+    pub(crate) fn orientation(&self) -> Quaternion<f32> {
+        self.orientation
+    }
+
+    /// Sets the orientation as is, without clamping the pitch, so recorded
+    /// views play back exactly.
+    pub(crate) fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        self.orientation = orientation.normalize();
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
+    pub fn aperture(&self) -> f32 {
+        self.aperture
+    }
+
+    pub fn blades(&self) -> u32 {
+        self.blades
+    }
+
+    /// Moves the camera to `position` and levels it looking along
+    /// `forwards`, dropping any roll.
+    pub fn set_view(&mut self, position: [f32; 3], forwards: [f32; 3]) {
+        self.position = position.into();
+        self.orientation = level_orientation(forwards.into());
+        self.clamp_pitch();
+    }
+
+    /// Origin and direction of the pinhole ray through `pixel` of an image of
+    /// `size` pixels, matching the ray generation of the tracer. `None` for
+    /// pixels outside of a fisheye's image circle.
+    pub fn primary_ray(&self, pixel: [f32; 2], size: [f32; 2]) -> Option<([f32; 3], [f32; 3])> {
+        let (forwards, right, up) = self.basis();
+        let uniform = self.into_uniform(size[0] / size[1]);
+        let [half_width, half_height] = uniform.half_extent;
+
+        // -1 for the left eye, 1 for the right eye and 0 without stereo
+        let (eye, pixel, size) = match self.stereo.map(|s| s.layout) {
+            Some(StereoLayout::SideBySide) => {
+                let half = 0.5 * size[0];
+                let eye = if pixel[0] < half { -1.0 } else { 1.0 };
+                (eye, [pixel[0] % half, pixel[1]], [half, size[1]])
+            }
+            Some(StereoLayout::OverUnder) => {
+                let half = 0.5 * size[1];
+                let eye = if pixel[1] < half { -1.0 } else { 1.0 };
+                (eye, [pixel[0], pixel[1] % half], [size[0], half])
+            }
+            None => (0.0, pixel, size),
+        };
+        let eye_offset = eye * uniform.eye_offset;
+        let mut origin = self.position + eye_offset * right;
+
+        // Texture rows run top to bottom while the up vector points up
+        let u = 2.0 * pixel[0] / size[0] - 1.0;
+        let v = 1.0 - 2.0 * pixel[1] / size[1];
+
+        let direction = match self.projection {
+            // Shifting the image plane instead of turning the eyes keeps
+            // vertical lines parallel
+            Projection::Perspective => {
+                let x = u * half_width - eye_offset * uniform.eye_convergence;
+                forwards + x * right + v * half_height * up
+            }
+            Projection::Orthographic { .. } => {
+                let origin = origin + u * half_width * right + v * half_height * up;
+                return Some((origin.into(), forwards.into()));
+            }
+            Projection::Equirectangular => {
+                let longitude = u * std::f32::consts::PI;
+                let latitude = v * std::f32::consts::FRAC_PI_2;
+                let horizontal = longitude.cos() * forwards + longitude.sin() * right;
+                let direction = latitude.cos() * horizontal + latitude.sin() * up;
+
+                // The eyes sit on a circle around the camera, perpendicular
+                // to the view direction. Towards the poles they move closer
+                // together, as there is no consistent left and right there.
+                let tangent = longitude.cos() * right - longitude.sin() * forwards;
+                let offset = eye_offset * latitude.cos();
+                origin = self.position + offset * tangent;
+                direction - offset * uniform.eye_convergence * tangent
+            }
+            Projection::Fisheye { mapping, .. } => {
+                let radius = (u * u + v * v).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let angle = match mapping {
+                    FisheyeMapping::Equidistant => radius * half_width,
+                    FisheyeMapping::Equisolid => 2.0 * (radius * (0.5 * half_width).sin()).asin(),
+                };
+                let (u, v) = if radius > 0.0 { (u / radius, v / radius) } else { (0.0, 0.0) };
+                angle.cos() * forwards + angle.sin() * (u * right + v * up)
+            }
+            Projection::CubeMap => {
+                let column = (pixel[0] / size[0] * 3.0).clamp(0.0, 2.999);
+                let row = (pixel[1] / size[1] * 2.0).clamp(0.0, 1.999);
+                let u = 2.0 * column.fract() - 1.0;
+                let v = 1.0 - 2.0 * row.fract();
+                let (face, face_right, face_up) = match (row as u32, column as u32) {
+                    (0, 0) => (right, -forwards, up),
+                    (0, 1) => (-right, forwards, up),
+                    (0, _) => (up, right, -forwards),
+                    (1, 0) => (-up, right, forwards),
+                    (1, 1) => (forwards, right, up),
+                    _ => (-forwards, -right, up),
+                };
+                face + u * face_right + v * face_up
+            }
+        };
+
+        Some((origin.into(), direction.normalize().into()))
+    }
+
+    /// Tangents of half the horizontal and vertical field of view.
+    fn perspective_half_extent(&self, aspect: f32) -> [f32; 2] {
+        match self.fov {
+            Fov::Vertical(degrees) => {
+                let half_height = (0.5 * degrees.to_radians()).tan();
+                [half_height * aspect, half_height]
+            }
+            Fov::Horizontal(degrees) => {
+                let half_width = (0.5 * degrees.to_radians()).tan();
+                [half_width, half_width / aspect]
+            }
+        }
+    }
+
+    /// Unit forwards, right and up vectors. They come straight from the
+    /// orientation, so they stay valid when looking straight up or down.
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        (
+            self.orientation.rotate_vector(Vector3::unit_x()),
+            self.orientation.rotate_vector(-Vector3::unit_y()),
+            self.orientation.rotate_vector(Vector3::unit_z()),
         )
     }
 
-    pub fn forwards(&mut self) {
-        self.position += 0.1*self.forwards;
+    /// Unit forwards, right and up vectors of the view.
+    pub fn axes(&self) -> ([f32; 3], [f32; 3], [f32; 3]) {
+        let (forwards, right, up) = self.basis();
+        (forwards.into(), right.into(), up.into())
+    }
+
+    /// Half the width and height of the visible area on the plane
+    /// `distance` in front of the camera, for an image of `aspect`.
+    pub fn half_extent_at(&self, distance: f32, aspect: f32) -> [f32; 2] {
+        match self.projection {
+            Projection::Orthographic { view_height } => [0.5 * view_height * aspect, 0.5 * view_height],
+            _ => self.perspective_half_extent(aspect).map(|e| e * distance),
+        }
+    }
+
+    /// Unit vector the camera looks along.
+    pub fn direction(&self) -> [f32; 3] {
+        self.basis().0.into()
     }
 
-    pub fn backwards(&mut self) {
-        self.position -= 0.1*self.forwards;
+    /// Angle between the view direction and the horizon, positive upwards.
+    pub fn pitch(&self) -> Rad<f32> {
+        // More precise than the arcsine close to the poles
+        let forwards = self.basis().0;
+        Rad(forwards.z.atan2(forwards.x.hypot(forwards.y)))
     }
 
-    pub fn rightwards(&mut self) {
-        let true_up = Vector3::unit_z();
-        let right = self.forwards.cross(true_up).normalize();
-        self.position += 0.1 * right;
+    /// Focuses on whatever lies `distance` along `direction`.
+    pub fn focus_along(&mut self, direction: [f32; 3], distance: f32) {
+        let direction: Vector3<f32> = direction.into();
+        // The focal plane is perpendicular to the view direction
+        self.focus_distance = distance * direction.dot(self.basis().0);
     }
 
-    pub fn leftwards(&mut self) {
-        let true_up = Vector3::unit_z();
-        let right = self.forwards.cross(true_up).normalize();
-        self.position -= 0.1 * right;
+    /// Moves by `[forwards, right, up]` in the camera's frame. In first
+    /// person mode up is the world's vertical axis.
+    pub fn translate(&mut self, [forwards, right, up]: [f32; 3]) {
+        let (forwards_axis, right_axis, up_axis) = self.basis();
+        let up_axis = match self.look_mode {
+            LookMode::FirstPerson => Vector3::unit_z(),
+            LookMode::Free => up_axis,
+        };
+        self.position += forwards * forwards_axis + right * right_axis + up * up_axis;
     }
 
+    /// Turns right by `speed` full turns, around the world's vertical axis in
+    /// first person mode and around the camera's up vector in free mode.
     pub fn rotate_rightwards(&mut self, speed: f32) {
         let angle: Rad<f32> = Rad::full_turn() * speed;
-        self.forwards = Basis3::from_angle_z(-angle)
-            .rotate_vector(self.forwards);
+        self.orientation = match self.look_mode {
+            LookMode::FirstPerson => Quaternion::from_angle_z(-angle) * self.orientation,
+            LookMode::Free => self.orientation * Quaternion::from_angle_z(-angle),
+        };
+        self.orientation = self.orientation.normalize();
     }
 
+    /// Turns up by `speed` full turns. In first person mode the pitch is
+    /// clamped short of the poles.
     pub fn rotate_upwards(&mut self, speed: f32) {
         let angle: Rad<f32> = Rad::full_turn() * speed;
-        let axis = self.forwards.cross(Vector3::unit_z()).normalize();
-        self.forwards = Basis3::from_axis_angle(axis, angle)
-            .rotate_vector(self.forwards);
+        match self.look_mode {
+            LookMode::FirstPerson => {
+                let max = Rad::from(MAX_PITCH);
+                let pitch = self.pitch();
+                let target = Rad((pitch + angle).0.clamp(-max.0, max.0));
+                self.pitch_by(target - pitch);
+            }
+            LookMode::Free => {
+                self.orientation = (self.orientation * Quaternion::from_angle_y(-angle)).normalize();
+            }
+        }
+    }
+
+    /// Rolls clockwise by `speed` full turns, around the view direction.
+    pub fn roll_rightwards(&mut self, speed: f32) {
+        let angle: Rad<f32> = Rad::full_turn() * speed;
+        self.orientation = (self.orientation * Quaternion::from_angle_x(angle)).normalize();
+    }
+
+    /// Degrees the camera is rolled clockwise from level, in `(-180, 180]`.
+    pub fn roll(&self) -> f32 {
+        // What is left after levelling is a rotation around the view
+        // direction, the camera's x axis
+        let mut relative = level_orientation(self.basis().0).invert() * self.orientation;
+        if relative.s < 0.0 {
+            relative = -relative;
+        }
+        Deg::from(Rad(2.0 * relative.v.x.atan2(relative.s))).0
+    }
+
+    /// Tilts the view direction up by `angle` around the horizontal axis,
+    /// which leaves the roll alone.
+    fn pitch_by(&mut self, angle: Rad<f32>) {
+        let (forwards, right, _) = self.basis();
+        // Straight up or down any horizontal axis works, the right vector is
+        // one of them
+        let horizontal = forwards.cross(Vector3::unit_z());
+        let axis = if horizontal.magnitude2() > 1e-8 { horizontal.normalize() } else { right };
+        self.orientation = (Quaternion::from_axis_angle(axis, angle) * self.orientation).normalize();
+    }
+
+    fn clamp_pitch(&mut self) {
+        if self.look_mode == LookMode::FirstPerson {
+            let max = Rad::from(MAX_PITCH);
+            let pitch = self.pitch();
+            self.pitch_by(Rad(pitch.0.clamp(-max.0, max.0)) - pitch);
+        }
     }
 }
 
+/// Orientation looking along `forwards` without roll.
+fn level_orientation(forwards: Vector3<f32>) -> Quaternion<f32> {
+    let forwards = forwards.normalize();
+    let yaw = Rad(forwards.y.atan2(forwards.x));
+    let pitch = Rad(forwards.z.clamp(-1.0, 1.0).asin());
+    Quaternion::from_angle_z(yaw) * Quaternion::from_angle_y(-pitch)
+}
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
 pub struct CameraUniform {
     position: [f32; 3],
-    _position_padding: f32,
+    aperture: f32,
     forwards: [f32; 3],
-    _forwards_padding: f32,
+    focus_distance: f32,
     right: [f32; 3],
-    _right_padding: f32,
+    blades: u32,
     up: [f32; 3],
-    _up_padding: f32,
+    projection: u32,
+    // Half the width and height of the image plane at unit distance for
+    // perspective, or of the visible area for orthographic projection
+    half_extent: [f32; 2],
+    // Half the interpupillary distance and its inverse convergence distance
+    eye_offset: f32,
+    eye_convergence: f32,
+    stereo: u32,
+    shutter: [f32; 2],
+    _padding: u32,
+}
+
+impl CameraUniform {
+    /// Tangents of half the horizontal and vertical field of view, `None`
+    /// for projections other than perspective.
+    pub fn perspective_half_extent(&self) -> Option<[f32; 2]> {
+        (self.projection == PROJECTION_PERSPECTIVE).then_some(self.half_extent)
+    }
 }
 
 impl Default for Camera {
@@ -84,20 +692,210 @@ impl Default for Camera {
     }
 }
 
-impl CameraUniform {
-    pub const fn new(position: [f32; 3], forwards: [f32; 3], right: [f32; 3], up: [f32; 3]) -> CameraUniform {
-        CameraUniform {
-            position,
-            forwards,
-            right,
-            up,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn angle_between(a: [f32; 3], b: [f32; 3]) -> f32 {
+        Vector3::from(a).angle(Vector3::from(b)).0.to_degrees()
+    }
 
-            _position_padding: 0.0,
-            _forwards_padding: 0.0,
-            _right_padding: 0.0,
-            _up_padding: 0.0,
+    #[test]
+    fn vertical_fov_spans_image_height() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_fov(Fov::Vertical(90.0));
+        let (_, top) = camera.primary_ray([100.0, 0.0], [200.0, 100.0]).unwrap();
+        let (_, bottom) = camera.primary_ray([100.0, 100.0], [200.0, 100.0]).unwrap();
+        assert!((angle_between(top, bottom) - 90.0).abs() < 1e-3);
+        assert!(top[2] > 0.0, "the first row looks upwards");
+    }
+
+    #[test]
+    fn horizontal_fov_spans_image_width() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [0.0, 2.0, 0.0]).with_fov(Fov::Horizontal(60.0));
+        for size in [[100.0, 100.0], [300.0, 100.0], [100.0, 300.0]] {
+            let (_, left) = camera.primary_ray([0.0, size[1] / 2.0], size).unwrap();
+            let (_, right) = camera.primary_ray([size[0], size[1] / 2.0], size).unwrap();
+            assert!((angle_between(left, right) - 60.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0])
+            .with_projection(Projection::Orthographic { view_height: 4.0 });
+        let (top_origin, top) = camera.primary_ray([100.0, 0.0], [200.0, 100.0]).unwrap();
+        let (left_origin, left) = camera.primary_ray([0.0, 50.0], [200.0, 100.0]).unwrap();
+        assert_eq!(top, [1.0, 0.0, 0.0]);
+        assert_eq!(left, [1.0, 0.0, 0.0]);
+        assert!((top_origin[2] - 2.0).abs() < 1e-5);
+        assert!((left_origin[1] - 4.0).abs() < 1e-5);
+    }
+
+    fn assert_direction(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(angle_between(actual, expected) < 1e-2, "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_projection(Projection::Equirectangular);
+        let [width, height] = camera.image_size(400, 123);
+        assert_eq!([width, height], [400, 200]);
+        let size = [width as f32, height as f32];
+
+        let ray = |x: f32, y: f32| camera.primary_ray([x, y], size).unwrap().1;
+        assert_direction(ray(200.0, 100.0), [1.0, 0.0, 0.0]);
+        assert_direction(ray(300.0, 100.0), [0.0, -1.0, 0.0]);
+        assert_direction(ray(0.0, 100.0), [-1.0, 0.0, 0.0]);
+        assert_direction(ray(200.0, 0.0), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn fisheye_edge_is_half_the_field_of_view() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0])
+                .with_projection(Projection::Fisheye { mapping, fov: 180.0 });
+            assert_eq!(camera.image_size(300, 100), [300, 300]);
+
+            let (_, edge) = camera.primary_ray([300.0, 150.0], [300.0, 300.0]).unwrap();
+            assert_direction(edge, [0.0, -1.0, 0.0]);
+            assert!(camera.primary_ray([0.0, 0.0], [300.0, 300.0]).is_none());
+        }
+    }
+
+    #[test]
+    fn cube_map_faces_look_along_the_axes() {
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_projection(Projection::CubeMap);
+        assert_eq!(camera.image_size(300, 300), [300, 200]);
+
+        let face = |column: f32, row: f32| camera.primary_ray([100.0 * column + 50.0, 100.0 * row + 50.0], [300.0, 200.0]).unwrap().1;
+        assert_direction(face(0.0, 0.0), [0.0, -1.0, 0.0]);
+        assert_direction(face(1.0, 0.0), [0.0, 1.0, 0.0]);
+        assert_direction(face(2.0, 0.0), [0.0, 0.0, 1.0]);
+        assert_direction(face(0.0, 1.0), [0.0, 0.0, -1.0]);
+        assert_direction(face(1.0, 1.0), [1.0, 0.0, 0.0]);
+        assert_direction(face(2.0, 1.0), [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn stereo_eyes_converge_on_the_screen_plane() {
+        let stereo = Stereo::new(0.5, 4.0, StereoLayout::SideBySide).unwrap();
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_stereo(stereo);
+        let size = [400.0, 100.0];
+
+        let (left_origin, left) = camera.primary_ray([100.0, 50.0], size).unwrap();
+        let (right_origin, right) = camera.primary_ray([300.0, 50.0], size).unwrap();
+        assert_eq!(left_origin, [0.0, 0.25, 0.0]);
+        assert_eq!(right_origin, [0.0, -0.25, 0.0]);
+
+        // Both centre rays pass through the point at the convergence distance
+        for (origin, direction) in [(left_origin, left), (right_origin, right)] {
+            let t = 4.0 / direction[0];
+            assert!((origin[1] + t * direction[1]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_converge_in_front_of_the_camera() {
+        for convergence in [0.0, -1.0, f32::NAN] {
+            assert!(Stereo::new(0.5, convergence, StereoLayout::SideBySide).is_err(), "{convergence}");
         }
     }
 
+    #[test]
+    fn fields_of_view_and_view_heights_are_checked() {
+        for degrees in [0.0, -10.0, 180.0, 270.0, f32::NAN] {
+            assert!(Fov::Vertical(degrees).validate().is_err(), "{degrees}");
+            assert!(Fov::Horizontal(degrees).validate().is_err(), "{degrees}");
+        }
+        assert!(Fov::Vertical(179.0).validate().is_ok());
+        for view_height in [0.0, -1.0, f32::NAN] {
+            assert!(Projection::Orthographic { view_height }.validate().is_err(), "{view_height}");
+        }
+        assert!(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 0.0 }.validate().is_err());
+        assert!(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 200.0 }.validate().is_ok());
+    }
+
+    #[test]
+    fn omni_directional_stereo_circles_the_camera() {
+        let stereo = Stereo::new(0.5, f32::INFINITY, StereoLayout::OverUnder).unwrap();
+        let camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0])
+            .with_projection(Projection::Equirectangular)
+            .with_stereo(stereo);
+        assert_eq!(camera.image_size(400, 123), [400, 400]);
+
+        // Left eye looking right, on the upper half
+        let (origin, direction) = camera.primary_ray([300.0, 100.0], [400.0, 400.0]).unwrap();
+        assert_direction(direction, [0.0, -1.0, 0.0]);
+        assert!((Vector3::from(origin) - Vector3::new(0.25, 0.0, 0.0)).magnitude() < 1e-5);
+
+        // The eyes meet at the poles
+        let (origin, _) = camera.primary_ray([200.0, 0.0], [400.0, 400.0]).unwrap();
+        assert!(Vector3::from(origin).magnitude() < 1e-5);
+    }
+
+    fn assert_finite(camera: &Camera) {
+        let uniform = camera.into_uniform(1.0);
+        for vector in [uniform.forwards, uniform.right, uniform.up] {
+            assert!(vector.iter().all(|v| v.is_finite()), "{:?} has an invalid basis", camera);
+        }
+    }
+
+    #[test]
+    fn looking_straight_up_or_down_keeps_a_valid_basis() {
+        for z in [1.0, -1.0] {
+            let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_look_mode(LookMode::Free);
+            camera.rotate_upwards(0.25 * z);
+            assert_finite(&camera);
+            assert_direction(camera.direction(), [0.0, 0.0, z]);
+
+            // First person mode tilts the camera back towards the horizon
+            let camera = Camera::new([0.0, 0.0, 0.0], [0.0, 0.0, z]);
+            assert_finite(&camera);
+            assert!((camera.pitch().0.abs() - Rad::from(MAX_PITCH).0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn first_person_pitch_stops_short_of_the_poles() {
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        for _ in 0..100 {
+            camera.rotate_upwards(0.01);
+            assert_finite(&camera);
+        }
+        assert!((camera.pitch() - Rad::from(MAX_PITCH)).0.abs() < 1e-4);
+
+        camera.rotate_rightwards(0.25);
+        assert_finite(&camera);
+        assert!(camera.direction()[1] < 0.0, "turning right at the pole still turns right");
+
+        camera.rotate_upwards(-1.0);
+        assert!((camera.pitch() + Rad::from(MAX_PITCH)).0.abs() < 1e-4);
+    }
+
+    #[test]
+    fn free_look_passes_over_the_poles() {
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).with_look_mode(LookMode::Free);
+        camera.rotate_upwards(0.25);
+        assert_direction(camera.direction(), [0.0, 0.0, 1.0]);
+        assert_finite(&camera);
+
+        camera.rotate_upwards(0.25);
+        assert_direction(camera.direction(), [-1.0, 0.0, 0.0]);
+        let (_, _, up) = camera.basis();
+        assert_direction(up.into(), [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn roll_turns_the_image_around_the_view_direction() {
+        let mut camera = Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        camera.roll_rightwards(0.25);
+        let (forwards, right, up) = camera.basis();
+        assert_direction(forwards.into(), [1.0, 0.0, 0.0]);
+        assert_direction(up.into(), [0.0, -1.0, 0.0]);
+        assert_direction(right.into(), [0.0, 0.0, -1.0]);
 
+        // Pitching a rolled first person camera still tilts towards the sky
+        camera.rotate_upwards(0.05);
+        assert!(camera.pitch().0 > 0.0);
+    }
 }
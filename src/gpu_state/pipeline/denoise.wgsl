@@ -0,0 +1,86 @@
+// One pass of the edge-avoiding à-trous wavelet filter (Dammertz et al.
+// 2010). Every pass blurs with a 5 by 5 B3 spline whose taps are `step`
+// pixels apart, and weighs each tap down by how much its colour, normal,
+// depth and albedo differ from the centre, so edges stay sharp.
+
+@group(0) @binding(0) var input: texture_2d<f32>;
+@group(0) @binding(1) var output: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(2) var aovs: texture_2d_array<f32>;
+@group(0) @binding(3) var<uniform> filterPass: FilterPass;
+
+const AOV_DEPTH: i32 = 0;
+const AOV_NORMAL: i32 = 1;
+const AOV_ALBEDO: i32 = 2;
+
+// Sharpness of the normal weight, depth tolerance relative to the depth per
+// pixel of offset, and the squared albedo difference halving the weight
+const NORMAL_POWER: f32 = 64.0;
+const DEPTH_SIGMA: f32 = 0.02;
+const ALBEDO_PHI: f32 = 0.01;
+
+struct FilterPass {
+    step: i32,
+    // Squared colour difference halving the weight, shrinks every pass
+    colorPhi: f32,
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(input);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let center: vec2<i32> = vec2<i32>(id.xy);
+
+    let color: vec3<f32> = textureLoad(input, center, 0).rgb;
+    let normal: vec3<f32> = textureLoad(aovs, center, AOV_NORMAL, 0).xyz;
+    let depth: f32 = textureLoad(aovs, center, AOV_DEPTH, 0).x;
+    let albedo: vec3<f32> = textureLoad(aovs, center, AOV_ALBEDO, 0).rgb;
+
+    var kernel: array<f32, 3> = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+    var sum: vec3<f32> = vec3(0.0);
+    var weightSum: f32 = 0.0;
+
+    for (var y: i32 = -2; y <= 2; y++) {
+        for (var x: i32 = -2; x <= 2; x++) {
+            let offset: vec2<i32> = vec2(x, y) * filterPass.step;
+            let position: vec2<i32> = center + offset;
+            if (any(position < vec2(0)) || any(position >= vec2<i32>(size))) {
+                continue;
+            }
+
+            let sampleColor: vec3<f32> = textureLoad(input, position, 0).rgb;
+            let sampleNormal: vec3<f32> = textureLoad(aovs, position, AOV_NORMAL, 0).xyz;
+            let sampleDepth: f32 = textureLoad(aovs, position, AOV_DEPTH, 0).x;
+            let sampleAlbedo: vec3<f32> = textureLoad(aovs, position, AOV_ALBEDO, 0).rgb;
+
+            // Colours are compressed first so fireflies do not stop the blur
+            let colorDifference: vec3<f32> = compress(sampleColor) - compress(color);
+            let colorWeight: f32 = exp(-dot(colorDifference, colorDifference) / filterPass.colorPhi);
+            let normalWeight: f32 = pow(max(dot(sampleNormal, normal), 0.0), NORMAL_POWER);
+            // Relative, so the background far away compares equal to itself
+            let depthDifference: f32 = abs(sampleDepth - depth) / max(depth, 1e-3);
+            let depthWeight: f32 = exp(-depthDifference / (DEPTH_SIGMA * length(vec2<f32>(offset)) + 1e-4));
+            let albedoDifference: vec3<f32> = sampleAlbedo - albedo;
+            let albedoWeight: f32 = exp(-dot(albedoDifference, albedoDifference) / ALBEDO_PHI);
+
+            // Misses have no normal, they only blur with each other
+            var geometryWeight: f32 = normalWeight;
+            if (all(normal == vec3(0.0)) && all(sampleNormal == vec3(0.0))) {
+                geometryWeight = 1.0;
+            }
+
+            let weight: f32 = kernel[abs(x)] * kernel[abs(y)] * colorWeight * geometryWeight * depthWeight * albedoWeight;
+            sum += weight * sampleColor;
+            weightSum += weight;
+        }
+    }
+
+    // The centre always has a positive weight
+    textureStore(output, center, vec4(sum / weightSum, 1.0));
+}
+
+fn compress(color: vec3<f32>) -> vec3<f32> {
+    let c: vec3<f32> = max(color, vec3(0.0));
+    return c / (1.0 + c);
+}
@@ -0,0 +1,260 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, Buffer, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Extent3d,
+    PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, Texture, TextureView,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+use super::RADIANCE_FORMAT;
+
+/// Each pass spreads its taps twice as far as the one before, five reach
+/// 32 pixels out.
+const PASSES: u32 = 5;
+
+/// Squared difference of compressed colours at which a tap's weight drops
+/// to 1/e in the first pass at one sample per pixel. Every further pass
+/// halves this difference. The variance of the noise falls with the number
+/// of samples, and so does the difference, so converged images are hardly
+/// blurred.
+const COLOR_PHI: f32 = 0.5;
+
+// Pixels per workgroup along each axis
+const WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FilterPassUniform {
+    step: i32,
+    color_phi: f32,
+    _padding: [u32; 2],
+}
+
+impl FilterPassUniform {
+    fn new(pass: u32, samples: u32) -> FilterPassUniform {
+        FilterPassUniform {
+            step: 1 << pass,
+            color_phi: COLOR_PHI / ((1 << pass) * samples.max(1)) as f32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Edge-avoiding à-trous filter smoothing the noise of the accumulated
+/// radiance, guided by the normal, depth and albedo AOVs so it does not blur
+/// across edges.
+pub struct Denoiser {
+    size: Extent3d,
+    pipeline: ComputePipeline,
+    // One per pass, passes alternate between writing the two textures
+    passes: Vec<BindGroup>,
+    uniforms: Vec<Buffer>,
+    // Samples per pixel the colour tolerance was last set for
+    samples: u32,
+    textures: [Texture; 2],
+}
+
+impl Denoiser {
+    pub fn new(device: &Device, size: Extent3d, radiance: &TextureView, aovs: &TextureView) -> Denoiser {
+        let textures = ["Denoise Texture A", "Denoise Texture B"].map(|label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: RADIANCE_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        });
+        let views = textures.each_ref().map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Denoise Shader"),
+            source: ShaderSource::Wgsl(include_str!("denoise.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: RADIANCE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("denoise_bind_group_layout"),
+        });
+
+        let uniforms: Vec<Buffer> = (0..PASSES)
+            .map(|i| {
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Filter Pass Buffer Descriptor"),
+                    contents: bytemuck::cast_slice(&[FilterPassUniform::new(i, 1)]),
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+                })
+            })
+            .collect();
+
+        let passes = (0..PASSES)
+            .map(|i| {
+                // The first pass reads the tracer's output
+                let input = if i == 0 { radiance } else { &views[(i as usize - 1) % 2] };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(input),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&views[i as usize % 2]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(aovs),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: uniforms[i as usize].as_entire_binding(),
+                        },
+                    ],
+                    label: Some("denoise_bind_group"),
+                })
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Denoise Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Denoise Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Denoiser {
+            size,
+            pipeline,
+            passes,
+            uniforms,
+            samples: 1,
+            textures,
+        }
+    }
+
+    /// Adapts the colour tolerance to an image averaging `samples` samples
+    /// per pixel.
+    pub fn set_samples(&mut self, queue: &Queue, samples: u32) {
+        if samples == self.samples {
+            return;
+        }
+        self.samples = samples;
+        for (i, buffer) in self.uniforms.iter().enumerate() {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[FilterPassUniform::new(i as u32, samples)]));
+        }
+    }
+
+    /// Filters the radiance texture into `output`.
+    pub fn run(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Denoise Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for bind_group in &self.passes {
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                self.size.width.div_ceil(WORKGROUP_SIZE),
+                self.size.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    /// Texture the last pass writes to.
+    pub fn output(&self) -> &Texture {
+        &self.textures[(PASSES as usize - 1) % 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_state::testing::{ball_on_ground, render, rms_error};
+    use crate::RenderOptions;
+
+    #[test]
+    fn denoising_brings_few_samples_closer_to_the_converged_image() {
+        let scene = ball_on_ground();
+        let denoised_options = RenderOptions {
+            denoise: true,
+            ..RenderOptions::default()
+        };
+
+        let Some(reference) = render(48, &scene, &RenderOptions::default(), 256) else { return };
+        let noisy = render(48, &scene, &RenderOptions::default(), 2).unwrap();
+        let denoised = render(48, &scene, &denoised_options, 2).unwrap();
+
+        let noisy_error = rms_error(&noisy, &reference);
+        let denoised_error = rms_error(&denoised, &reference);
+        assert!(denoised_error < 0.6 * noisy_error, "{denoised_error} against {noisy_error}");
+    }
+
+    #[test]
+    fn denoising_leaves_converged_images_almost_alone() {
+        let scene = ball_on_ground();
+        let denoised_options = RenderOptions {
+            denoise: true,
+            ..RenderOptions::default()
+        };
+
+        let Some(converged) = render(32, &scene, &RenderOptions::default(), 256) else { return };
+        let denoised = render(32, &scene, &denoised_options, 256).unwrap();
+        let reference = render(32, &scene, &RenderOptions { seed: 1, ..RenderOptions::default() }, 256).unwrap();
+
+        // Blurring the converged image away from the truth shows up against an
+        // independent render with another seed
+        let noise = rms_error(&converged, &reference);
+        let denoised_error = rms_error(&denoised, &reference);
+        assert!(denoised_error < 1.5 * noise, "{denoised_error} against {noise}");
+    }
+}
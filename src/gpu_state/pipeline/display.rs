@@ -0,0 +1,245 @@
+use bytemuck::{Pod, Zeroable};
+use super::aov::Aov;
+
+pub const TONE_MAPPER_CLAMP: u32 = 0;
+pub const TONE_MAPPER_REINHARD: u32 = 1;
+pub const TONE_MAPPER_ACES: u32 = 2;
+pub const TONE_MAPPER_AGX: u32 = 3;
+pub const TONE_MAPPER_UNCHARTED_2: u32 = 4;
+
+/// Curve squeezing high dynamic range radiance into what a screen can show.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Clips everything above one.
+    #[default]
+    Clamp,
+    /// `x / (1 + x)` per channel, never clips but looks flat.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    AcesFilmic,
+    /// Troy Sobotka's AgX, desaturates bright colours towards white instead
+    /// of skewing their hue.
+    AgX,
+    /// John Hable's filmic curve from Uncharted 2.
+    Uncharted2,
+}
+
+impl ToneMapper {
+    pub fn next(self) -> ToneMapper {
+        match self {
+            ToneMapper::Clamp => ToneMapper::Reinhard,
+            ToneMapper::Reinhard => ToneMapper::AcesFilmic,
+            ToneMapper::AcesFilmic => ToneMapper::AgX,
+            ToneMapper::AgX => ToneMapper::Uncharted2,
+            ToneMapper::Uncharted2 => ToneMapper::Clamp,
+        }
+    }
+
+    /// Maps linear radiance to linear display values in `[0, 1]`, exactly
+    /// like the display shader.
+    pub fn apply(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            ToneMapper::Clamp => rgb.map(|x| x.clamp(0.0, 1.0)),
+            ToneMapper::Reinhard => rgb.map(|x| {
+                let x = x.max(0.0);
+                x / (1.0 + x)
+            }),
+            ToneMapper::AcesFilmic => rgb.map(|x| {
+                let x = x.max(0.0);
+                (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }),
+            ToneMapper::AgX => agx(rgb),
+            ToneMapper::Uncharted2 => {
+                const WHITE: f32 = 11.2;
+                const EXPOSURE_BIAS: f32 = 2.0;
+                rgb.map(|x| (hable(EXPOSURE_BIAS * x.max(0.0)) / hable(WHITE)).clamp(0.0, 1.0))
+            }
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            ToneMapper::Clamp => TONE_MAPPER_CLAMP,
+            ToneMapper::Reinhard => TONE_MAPPER_REINHARD,
+            ToneMapper::AcesFilmic => TONE_MAPPER_ACES,
+            ToneMapper::AgX => TONE_MAPPER_AGX,
+            ToneMapper::Uncharted2 => TONE_MAPPER_UNCHARTED_2,
+        }
+    }
+}
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+// Columns of the AgX inset and outset matrices
+#[allow(clippy::excessive_precision)]
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.842479062253094, 0.0423282422610123, 0.0423756549057051],
+    [0.0784335999999992, 0.878468636469772, 0.0784336],
+    [0.0792237451477643, 0.0791661274605434, 0.879142973793104],
+];
+#[allow(clippy::excessive_precision)]
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.19687900512017, -0.0528968517574562, -0.0529716355144438],
+    [-0.0980208811401368, 1.15190312990417, -0.0980434501171241],
+    [-0.0990297440797205, -0.0989611768448433, 1.15107367264116],
+];
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn agx(rgb: [f32; 3]) -> [f32; 3] {
+    let multiply = |m: [[f32; 3]; 3], v: [f32; 3]| [0, 1, 2].map(|i| m[0][i] * v[0] + m[1][i] * v[1] + m[2][i] * v[2]);
+
+    let encoded = multiply(AGX_INSET, rgb).map(|x| {
+        let ev = x.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+        let x = (ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        // Polynomial fit of the default contrast sigmoid
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // The sigmoid works on gamma 2.2 encoded values
+    multiply(AGX_OUTSET, encoded).map(|x| x.max(0.0).powf(2.2).min(1.0))
+}
+
+/// How accumulated radiance is turned into pixels on screen and in PNGs.
+/// Changing it never restarts accumulation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Display {
+    /// Exposure compensation in stops, every stop doubles the brightness.
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Display {
+    /// Linear display values of a pixel, before the sRGB transfer function.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = self.exposure.exp2();
+        self.tone_mapper.apply(rgb.map(|x| x * scale))
+    }
+
+    /// `encode_srgb` is set for surfaces that take sRGB encoded values
+    /// without converting linear ones themselves. `aov` is shown instead of
+    /// the image if set.
+    pub fn into_uniform(self, encode_srgb: bool, aov: Option<Aov>) -> DisplayUniform {
+        DisplayUniform {
+            exposure_scale: self.exposure.exp2(),
+            tone_mapper: self.tone_mapper.id(),
+            encode_srgb: encode_srgb as u32,
+            aov: aov.map_or(0, |aov| aov.layer() + 1),
+        }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+}
+
+/// The sRGB transfer function.
+pub fn encode_srgb(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct DisplayUniform {
+    exposure_scale: f32,
+    tone_mapper: u32,
+    encode_srgb: u32,
+    // Layer of the AOV shown plus one, zero shows the image
+    aov: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPERS: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::AcesFilmic,
+        ToneMapper::AgX,
+        ToneMapper::Uncharted2,
+    ];
+
+    #[test]
+    fn tone_mappers_are_monotonic_and_stay_in_range() {
+        for mapper in MAPPERS {
+            let mut previous = mapper.apply([0.0; 3])[0];
+            assert!(previous < 0.01, "{mapper:?} lifts black to {previous}");
+            for i in 1..200 {
+                let x = 0.05 * i as f32;
+                let [y, _, _] = mapper.apply([x; 3]);
+                assert!((0.0..=1.0).contains(&y), "{mapper:?}({x}) = {y}");
+                assert!(y >= previous - 1e-6, "{mapper:?} falls at {x}");
+                previous = y;
+            }
+        }
+        assert_eq!(ToneMapper::Reinhard.apply([1.0, 3.0, 0.0]), [0.5, 0.75, 0.0]);
+        // Every curve but clamping keeps some contrast in the highlights
+        for mapper in &MAPPERS[1..] {
+            assert!(mapper.apply([4.0; 3])[0] < mapper.apply([16.0; 3])[0], "{mapper:?}");
+        }
+    }
+
+    #[test]
+    fn exposure_is_measured_in_stops() {
+        let display = Display {
+            exposure: 2.0,
+            tone_mapper: ToneMapper::Reinhard,
+        };
+        assert_eq!(display.apply([0.25; 3]), [0.5; 3]);
+        let mut mapper = ToneMapper::default();
+        for _ in 0..MAPPERS.len() {
+            mapper = mapper.next();
+        }
+        assert_eq!(mapper, ToneMapper::default());
+    }
+
+    #[test]
+    fn display_shader_matches_the_tone_mappers() {
+        use crate::gpu_state::testing::with_state;
+        use crate::{Camera, RenderOptions, Scene};
+
+        // Every pixel sees the background, exposures spread its channels
+        // over the whole range of the curves
+        let mut scene = Scene::new(Camera::default());
+        let radiance = [0.02, 0.3, 1.6];
+        scene.background = radiance;
+        with_state(4, &scene, &RenderOptions::default(), |state| {
+            state.render(1);
+            for tone_mapper in MAPPERS {
+                for exposure in [-6.0, -2.0, 0.0, 1.5, 4.0, 8.0] {
+                    let display = Display { exposure, tone_mapper };
+                    state.set_display(display);
+                    let expected = display.apply(radiance).map(|x| encode_srgb(x) * 255.0);
+                    for pixel in state.read_display() {
+                        for i in 0..3 {
+                            assert!(
+                                (pixel[i] as f32 - expected[i]).abs() <= 1.0,
+                                "{display:?}: shader gives {pixel:?}, ToneMapper::apply {expected:?}"
+                            );
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,419 @@
+use std::f32::consts::PI;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, Buffer, BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, Extent3d, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, TextureView,
+};
+use super::RADIANCE_FORMAT;
+
+pub const FILTER_BOX: u32 = 0;
+pub const FILTER_TENT: u32 = 1;
+pub const FILTER_GAUSSIAN: u32 = 2;
+pub const FILTER_MITCHELL: u32 = 3;
+pub const FILTER_BLACKMAN_HARRIS: u32 = 4;
+
+/// Bytes per sample in the sample buffer, radiance and offset padded to the
+/// alignment of a `vec3`.
+pub const SAMPLE_SIZE: wgpu::BufferAddress = 32;
+
+// Pixels per workgroup along each axis
+const WORKGROUP_SIZE: u32 = 8;
+
+// Midpoint rule steps integrating the filters for their normalisation
+const INTEGRATION_STEPS: u32 = 1024;
+
+/// Shape of the weight a sample gets in the pixels around it. All are
+/// separable, the weight is the product of the filter along both axes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Equal weight for every sample within the radius. With a radius of
+    /// half a pixel each sample only counts for the pixel it was taken in.
+    #[default]
+    Box,
+    /// Falls off linearly towards the radius.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted
+    /// down to reach zero there.
+    Gaussian,
+    /// Mitchell and Netravali's cubic with `B = C = 1/3`, sharper than the
+    /// Gaussian thanks to slightly negative lobes.
+    Mitchell,
+    /// Four-term Blackman–Harris window, smooth with little ringing.
+    BlackmanHarris,
+}
+
+impl Filter {
+    /// Radius usually used with the filter, in pixels.
+    pub fn default_radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::BlackmanHarris => 2.0,
+        }
+    }
+
+    /// Unnormalised weight at `x` pixels from the centre, exactly like the
+    /// splatting shader.
+    pub fn evaluate(self, x: f32, radius: f32) -> f32 {
+        let x = x.abs();
+        if x >= radius {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x / radius,
+            Filter::Gaussian => {
+                let gaussian = |x: f32| (-4.5 * x * x / (radius * radius)).exp();
+                gaussian(x) - gaussian(radius)
+            }
+            Filter::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+                let x = 2.0 * x / radius;
+                let cubic = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+                };
+                cubic / 6.0
+            }
+            Filter::BlackmanHarris => {
+                let t = 2.0 * PI * (0.5 + 0.5 * x / radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            Filter::Box => FILTER_BOX,
+            Filter::Tent => FILTER_TENT,
+            Filter::Gaussian => FILTER_GAUSSIAN,
+            Filter::Mitchell => FILTER_MITCHELL,
+            Filter::BlackmanHarris => FILTER_BLACKMAN_HARRIS,
+        }
+    }
+}
+
+/// How samples are reconstructed into pixels. Anything but a box of half a
+/// pixel spreads every sample over the pixels within `radius` of it, which
+/// trades a little sharpness for less aliasing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelFilter {
+    filter: Filter,
+    // Distance in pixels beyond which a sample has no weight
+    radius: f32,
+    // Makes the weights integrate to one, integrated whenever the filter or
+    // its radius change
+    normalization: f32,
+}
+
+impl PixelFilter {
+    /// `filter` with its usual radius.
+    pub fn new(filter: Filter) -> PixelFilter {
+        PixelFilter {
+            filter,
+            radius: 0.0,
+            normalization: 0.0,
+        }
+        .with_radius(filter.default_radius())
+    }
+
+    /// The same filter reaching `radius` pixels from the sample.
+    pub fn with_radius(self, radius: f32) -> PixelFilter {
+        PixelFilter {
+            radius,
+            normalization: normalization(self.filter, radius),
+            ..self
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Distance in pixels beyond which a sample has no weight.
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Weight of a sample `offset` pixels from a pixel's centre, scaled so
+    /// the weights integrate to one.
+    pub fn weight(&self, [x, y]: [f32; 2]) -> f32 {
+        self.normalization * self.filter.evaluate(x, self.radius) * self.filter.evaluate(y, self.radius)
+    }
+
+    /// Whether samples need weighting, otherwise the tracer accumulates them
+    /// directly. Only a box of exactly half a pixel gives every sample full
+    /// weight in its own pixel and none elsewhere, a smaller box drops the
+    /// samples near the pixel's edges.
+    pub fn splats(&self) -> bool {
+        self.filter != Filter::Box || self.radius != 0.5
+    }
+
+    /// `frame` is the index of the frame about to be traced, the first one
+    /// replaces the accumulated image.
+    pub fn into_uniform(self, frame: u32) -> FilterUniform {
+        FilterUniform {
+            filter: self.filter.id(),
+            radius: self.radius,
+            normalization: self.normalization,
+            frame,
+        }
+    }
+}
+
+impl Default for PixelFilter {
+    fn default() -> PixelFilter {
+        PixelFilter::new(Filter::Box)
+    }
+}
+
+// Scale of the separable filter making it integrate to one over the plane
+fn normalization(filter: Filter, radius: f32) -> f32 {
+    let step = 2.0 * radius / INTEGRATION_STEPS as f32;
+    let integral: f32 = (0..INTEGRATION_STEPS)
+        .map(|i| filter.evaluate(-radius + (i as f32 + 0.5) * step, radius) * step)
+        .sum();
+    1.0 / (integral * integral)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct FilterUniform {
+    filter: u32,
+    radius: f32,
+    normalization: f32,
+    frame: u32,
+}
+
+/// Adds the samples of a frame into the accumulation buffer, each weighted
+/// by the filter in every pixel within its radius. Every pixel gathers the
+/// samples of its neighbours rather than samples scattering themselves,
+/// which gives the same sums without atomics.
+pub struct Splatter {
+    size: Extent3d,
+    sample_buffer: Buffer,
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    uniform_buffer: Buffer,
+}
+
+impl Splatter {
+    pub fn new(device: &Device, size: Extent3d, accumulation_buffer: &Buffer, radiance: &TextureView) -> Splatter {
+        // The tracer writes a frame's samples here, with their position in
+        // the pixel
+        let sample_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sample Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * SAMPLE_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Filter Buffer"),
+            size: std::mem::size_of::<FilterUniform>() as wgpu::BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Splat Shader"),
+            source: ShaderSource::Wgsl(include_str!("splat.wgsl").into()),
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage(0, true),
+                storage(1, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: RADIANCE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("splat_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: sample_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(radiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("splat_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Splat Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Splat Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Splatter {
+            size,
+            sample_buffer,
+            pipeline,
+            bind_group,
+            uniform_buffer,
+        }
+    }
+
+    /// Buffer the tracer writes the samples to.
+    pub fn sample_buffer(&self) -> &Buffer {
+        &self.sample_buffer
+    }
+
+    pub fn write_uniform(&self, queue: &Queue, uniform: FilterUniform) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Splats the samples of the frame just traced, must come after it.
+    pub fn run(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Splat Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            self.size.width.div_ceil(WORKGROUP_SIZE),
+            self.size.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::PhysicalSize;
+    use crate::gpu_state::testing::ball_on_ground;
+    use crate::{HeadlessState, RenderOptions};
+
+    const FILTERS: [Filter; 5] = [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell, Filter::BlackmanHarris];
+
+    #[test]
+    fn filters_are_normalised_and_vanish_at_their_radius() {
+        for filter in FILTERS {
+            let pixel_filter = PixelFilter::new(filter);
+            let radius = pixel_filter.radius();
+            if filter != Filter::Box {
+                assert!(filter.evaluate(radius - 1e-3, radius).abs() < 1e-3, "{filter:?}");
+            }
+            assert_eq!(filter.evaluate(radius, radius), 0.0);
+            assert!(filter.evaluate(0.0, radius) > 0.0);
+
+            let steps = 256;
+            let step = 2.0 * radius / steps as f32;
+            let integral: f32 = (0..steps * steps)
+                .map(|i| {
+                    let x = -radius + ((i % steps) as f32 + 0.5) * step;
+                    let y = -radius + ((i / steps) as f32 + 0.5) * step;
+                    pixel_filter.weight([x, y]) * step * step
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-2, "{filter:?} integrates to {integral}");
+        }
+    }
+
+    #[test]
+    fn only_a_half_pixel_box_skips_splatting() {
+        assert!(!PixelFilter::default().splats());
+        for radius in [0.25, 1.0] {
+            assert!(PixelFilter::new(Filter::Box).with_radius(radius).splats(), "{radius}");
+        }
+        assert!(PixelFilter::new(Filter::Tent).with_radius(0.5).splats());
+    }
+
+    #[test]
+    fn splatting_matches_a_cpu_reference() {
+        const SIZE: u32 = 12;
+        const FRAMES: u32 = 3;
+        let scene = ball_on_ground();
+
+        // A box smaller than a pixel drops some samples altogether
+        let pixel_filters = FILTERS
+            .map(|filter| PixelFilter::new(filter).with_radius(filter.default_radius().max(1.0)))
+            .into_iter()
+            .chain([PixelFilter::new(Filter::Box).with_radius(0.25)]);
+        for pixel_filter in pixel_filters {
+            let filter = pixel_filter.filter();
+            let options = RenderOptions {
+                pixel_filter,
+                ..RenderOptions::default()
+            };
+            let Some(mut state) = HeadlessState::for_test(PhysicalSize::new(SIZE, SIZE), &scene, &options) else { return };
+
+            // Splats the very samples the GPU took
+            let mut sums = vec![[0.0f64; 4]; (SIZE * SIZE) as usize];
+            for _ in 0..FRAMES {
+                state.render(1);
+                for (i, (radiance, offset)) in state.read_samples().into_iter().enumerate() {
+                    let position = [(i as u32 % SIZE) as f32 + offset[0], (i as u32 / SIZE) as f32 + offset[1]];
+                    for (pixel, sum) in sums.iter_mut().enumerate() {
+                        let center = [(pixel as u32 % SIZE) as f32 + 0.5, (pixel as u32 / SIZE) as f32 + 0.5];
+                        let weight = pixel_filter.weight([position[0] - center[0], position[1] - center[1]]) as f64;
+                        for c in 0..3 {
+                            sum[c] += weight * radiance[c] as f64;
+                        }
+                        sum[3] += weight;
+                    }
+                }
+            }
+
+            for (pixel, (gpu, sum)) in state.read_radiance().iter().zip(&sums).enumerate() {
+                for c in 0..3 {
+                    let cpu = if sum[3] == 0.0 { 0.0 } else { (sum[c] / sum[3]) as f32 };
+                    assert!((gpu[c] - cpu).abs() <= 1e-3 * cpu.abs().max(1.0), "{filter:?} pixel {pixel}: {gpu:?} against {cpu}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use bytemuck::{Pod, Zeroable};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct FrameUniform {
+    index: u32,
+    max_bounces: u32,
+    // Scene time in seconds that animated objects are shown at
+    time: f32,
+    seed: u32,
+    // Set when samples go to the sample buffer to be splatted, instead of
+    // straight into the accumulation buffer
+    splat: u32,
+    // Which of the samplers paths draw their random numbers from
+    sampler: u32,
+    _padding: [u32; 2],
+}
+
+impl FrameUniform {
+    pub const fn new(index: u32, max_bounces: u32, time: f32, seed: u32, splat: bool, sampler: u32) -> FrameUniform {
+        FrameUniform {
+            index,
+            max_bounces,
+            time,
+            seed,
+            splat: splat as u32,
+            sampler,
+            _padding: [0; 2],
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::path::Path;
+use anyhow::{bail, ensure, Context, Result};
+use bytemuck::{Pod, Zeroable};
+
+/// Dense voxel grid of densities, stored with x varying fastest and z
+/// slowest. The grid is stretched over the bounding box of the object whose
+/// medium refers to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityGrid {
+    dimensions: [u32; 3],
+    densities: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn new(dimensions: [u32; 3], densities: Vec<f32>) -> Result<DensityGrid> {
+        let voxels = dimensions.iter().map(|&d| d as usize).product::<usize>();
+        ensure!(voxels > 0, "density grid {:?} has no voxels", dimensions);
+        ensure!(
+            densities.len() == voxels,
+            "density grid {:?} needs {} voxels but {} were given",
+            dimensions, voxels, densities.len(),
+        );
+        if let Some(density) = densities.iter().find(|d| !(d.is_finite() && **d >= 0.0)) {
+            bail!("density grid has invalid density {}", density);
+        }
+        Ok(DensityGrid { dimensions, densities })
+    }
+
+    /// Evaluates `density` at the centre of every voxel, in normalised
+    /// `[0, 1]` coordinates.
+    pub fn from_fn(dimensions: [u32; 3], density: impl Fn([f32; 3]) -> f32) -> Result<DensityGrid> {
+        let [nx, ny, nz] = dimensions;
+        let mut densities = Vec::with_capacity((nx * ny * nz) as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    densities.push(density([
+                        (x as f32 + 0.5) / nx as f32,
+                        (y as f32 + 0.5) / ny as f32,
+                        (z as f32 + 0.5) / nz as f32,
+                    ]));
+                }
+            }
+        }
+        DensityGrid::new(dimensions, densities)
+    }
+
+    /// Loads a grid from a file, picking the format from the extension:
+    ///
+    /// * `.vol` is Mitsuba's binary grid format, which is also what dense
+    ///   grids converted from OpenVDB are usually written as.
+    pub fn load(path: impl AsRef<Path>) -> Result<DensityGrid> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("vol") => DensityGrid::from_vol(&bytes),
+            _ => bail!("unknown density grid format: {}", path.display()),
+        }
+        .with_context(|| format!("loading {}", path.display()))
+    }
+
+    /// Loads a headerless file of little endian `f32` densities.
+    pub fn load_raw(path: impl AsRef<Path>, dimensions: [u32; 3]) -> Result<DensityGrid> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        DensityGrid::new(dimensions, read_f32s(&bytes)).with_context(|| format!("loading {}", path.display()))
+    }
+
+    /// Parses Mitsuba's `VOL` version 3 format. Multi-channel grids are
+    /// averaged down to a single density.
+    pub fn from_vol(bytes: &[u8]) -> Result<DensityGrid> {
+        ensure!(bytes.len() >= 48 && &bytes[0..3] == b"VOL", "not a VOL file");
+        ensure!(bytes[3] == 3, "unsupported VOL version {}", bytes[3]);
+
+        let header = |i: usize| i32::from_le_bytes(bytes[4 + 4 * i..8 + 4 * i].try_into().unwrap());
+        let encoding = header(0);
+        let dimensions = [header(1), header(2), header(3)];
+        let channels = header(4);
+        ensure!(dimensions.iter().all(|&d| d > 0) && channels > 0, "invalid VOL dimensions");
+
+        // The bounding box is ignored, the grid is fitted to its object
+        let data = &bytes[48..];
+        let values = match encoding {
+            1 => read_f32s(data),
+            3 => data.iter().map(|&b| b as f32 / 255.0).collect(),
+            _ => bail!("unsupported VOL encoding {}", encoding),
+        };
+
+        let channels = channels as usize;
+        let densities = values
+            .chunks_exact(channels)
+            .map(|voxel| voxel.iter().sum::<f32>() / channels as f32)
+            .collect();
+        DensityGrid::new(dimensions.map(|d| d as u32), densities)
+    }
+
+    pub fn dimensions(&self) -> [u32; 3] {
+        self.dimensions
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.densities.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GridStorage {
+    dimensions: [u32; 3],
+    // First slice of the grid in the atlas
+    offset: u32,
+    max_density: f32,
+    _padding: [f32; 3],
+}
+
+/// All grids of a scene stacked along z into one 3D texture.
+pub struct GridAtlas {
+    pub size: wgpu::Extent3d,
+    pub densities: Vec<f32>,
+    pub grids: Vec<GridStorage>,
+}
+
+impl GridAtlas {
+    /// Fails if the texture would be larger than `max_size`, the device's
+    /// `max_texture_dimension_3d`, in any dimension.
+    pub fn new(grids: &[DensityGrid], max_size: u32) -> Result<GridAtlas> {
+        let width = grids.iter().map(|g| g.dimensions[0]).max().unwrap_or(1);
+        let height = grids.iter().map(|g| g.dimensions[1]).max().unwrap_or(1);
+        let depth = grids.iter().map(|g| g.dimensions[2]).sum::<u32>().max(1);
+        ensure!(
+            width <= max_size && height <= max_size && depth <= max_size,
+            "density grids stack to {}x{}x{} voxels, more than the {} the device allows",
+            width,
+            height,
+            depth,
+            max_size
+        );
+
+        let mut densities = vec![0.0; (width * height * depth) as usize];
+        let mut storage = Vec::with_capacity(grids.len());
+        let mut offset = 0;
+
+        for grid in grids {
+            let [nx, ny, nz] = grid.dimensions;
+            for z in 0..nz {
+                for y in 0..ny {
+                    let source = ((z * ny + y) * nx) as usize;
+                    let destination = (((offset + z) * height + y) * width) as usize;
+                    densities[destination..destination + nx as usize]
+                        .copy_from_slice(&grid.densities[source..source + nx as usize]);
+                }
+            }
+
+            storage.push(GridStorage {
+                dimensions: grid.dimensions,
+                offset,
+                max_density: grid.max_density(),
+                _padding: [0.0; 3],
+            });
+            offset += nz;
+        }
+
+        // Storage bindings can not be empty
+        if storage.is_empty() {
+            storage.push(GridStorage::zeroed());
+        }
+
+        Ok(GridAtlas {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: depth,
+            },
+            densities,
+            grids: storage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vol(encoding: i32, dimensions: [i32; 3], channels: i32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for value in [encoding, dimensions[0], dimensions[1], dimensions[2], channels] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parses_float_vol() {
+        let data: Vec<u8> = [0.0f32, 0.5, 1.0, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let grid = DensityGrid::from_vol(&vol(1, [2, 2, 1], 1, &data)).unwrap();
+        assert_eq!(grid.dimensions(), [2, 2, 1]);
+        assert_eq!(grid.densities, vec![0.0, 0.5, 1.0, 2.0]);
+        assert_eq!(grid.max_density(), 2.0);
+    }
+
+    #[test]
+    fn averages_vol_channels() {
+        let grid = DensityGrid::from_vol(&vol(3, [1, 1, 2], 3, &[0, 255, 255, 0, 0, 0])).unwrap();
+        assert_eq!(grid.densities, vec![2.0 / 3.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_truncated_vol() {
+        assert!(DensityGrid::from_vol(&vol(1, [2, 2, 2], 1, &[0; 12])).is_err());
+        assert!(DensityGrid::from_vol(b"VOL\x03").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_grids_and_invalid_densities() {
+        assert!(DensityGrid::from_fn([0, 2, 2], |_| 1.0).is_err());
+        assert!(DensityGrid::from_fn([2, 2, 2], |[x, _, _]| x - 0.5).is_err());
+        assert!(DensityGrid::from_fn([2, 2, 2], |_| f32::NAN).is_err());
+        assert!(DensityGrid::new([1, 1, 2], vec![1.0, f32::INFINITY]).is_err());
+    }
+
+    #[test]
+    fn stacks_grids_along_z() {
+        let a = DensityGrid::from_fn([2, 1, 1], |_| 1.0).unwrap();
+        let b = DensityGrid::from_fn([1, 2, 2], |_| 2.0).unwrap();
+        let atlas = GridAtlas::new(&[a.clone(), b.clone()], 3).unwrap();
+
+        assert_eq!((atlas.size.width, atlas.size.height, atlas.size.depth_or_array_layers), (2, 2, 3));
+        assert_eq!(atlas.densities, vec![1.0, 1.0, 0.0, 0.0, 2.0, 0.0, 2.0, 0.0, 2.0, 0.0, 2.0, 0.0]);
+        assert_eq!(atlas.grids[1].offset, 1);
+
+        assert!(GridAtlas::new(&[a, b], 2).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+/// Principled (Disney-style) material. All parameters except `ior` and
+/// `emission` are expected to lie in `[0, 1]`.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    pub base_color: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Strength of the dielectric specular reflection, `0.5` maps to an
+    /// F0 of 4% which matches most common materials.
+    pub specular: f32,
+    pub specular_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    pub emission: [f32; 3],
+}
+
+impl Material {
+    pub fn diffuse(base_color: [f32; 3]) -> Material {
+        Material {
+            base_color,
+            roughness: 1.0,
+            specular: 0.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn metal(base_color: [f32; 3], roughness: f32) -> Material {
+        Material {
+            base_color,
+            metallic: 1.0,
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    pub fn glass(ior: f32, roughness: f32) -> Material {
+        Material {
+            base_color: [1.0, 1.0, 1.0],
+            roughness,
+            transmission: 1.0,
+            ior,
+            ..Default::default()
+        }
+    }
+
+    pub fn emissive(emission: [f32; 3]) -> Material {
+        Material {
+            base_color: [0.0, 0.0, 0.0],
+            specular: 0.0,
+            emission,
+            ..Default::default()
+        }
+    }
+
+    pub fn into_storage(self) -> MaterialStorage {
+        MaterialStorage {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            emission: self.emission,
+            roughness: self.roughness,
+            specular: self.specular,
+            specular_tint: self.specular_tint,
+            clearcoat: self.clearcoat,
+            clearcoat_roughness: self.clearcoat_roughness,
+            sheen: self.sheen,
+            sheen_tint: self.sheen_tint,
+            transmission: self.transmission,
+            ior: self.ior,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            base_color: [0.8, 0.8, 0.8],
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            transmission: 0.0,
+            ior: 1.5,
+            emission: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct MaterialStorage {
+    base_color: [f32; 3],
+    metallic: f32,
+    emission: [f32; 3],
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    transmission: f32,
+    ior: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Camera, Material, Object, RenderOptions, Scene};
+    use crate::gpu_state::testing::{mean, render};
+
+    /// Renders a single sphere lit by the uniform white sky and returns the
+    /// mean brightness of the image. Returns `None` if GPU tests are skipped.
+    fn furnace(material: Material) -> Option<f32> {
+        let mut scene = Scene::new(Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        scene.add(Object::sphere([3.0, 0.0, 0.0], 1.0, material)).unwrap();
+        render(16, &scene, &RenderOptions::default(), 64).map(|radiance| mean(&radiance))
+    }
+
+    // Single scattering microfacet lobes lose some energy at high roughness,
+    // which is why the lower bounds are not all close to one.
+    #[test]
+    fn white_furnace() {
+        let white = [1.0, 1.0, 1.0];
+        let materials = [
+            ("diffuse", Material::diffuse(white), 0.97),
+            ("plastic", Material { base_color: white, ..Default::default() }, 0.97),
+            ("metal", Material::metal(white, 0.3), 0.98),
+            ("rough metal", Material::metal(white, 1.0), 0.7),
+            ("glass", Material::glass(1.5, 0.0), 0.99),
+            ("rough glass", Material::glass(1.5, 0.6), 0.8),
+            ("coated", Material { base_color: white, clearcoat: 1.0, sheen: 1.0, sheen_tint: 0.0, ..Default::default() }, 0.97),
+        ];
+
+        for (name, material, lower_bound) in materials {
+            let Some(mean) = furnace(material) else { return };
+            assert!(mean <= 1.005, "{name} material gains energy: {mean}");
+            assert!(mean >= lower_bound, "{name} material loses too much energy: {mean}");
+        }
+    }
+}
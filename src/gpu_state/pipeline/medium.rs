@@ -0,0 +1,146 @@
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+/// Participating medium. Coefficients are per unit of scene distance,
+/// `anisotropy` is the Henyey–Greenstein `g` in `(-1, 1)`.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Medium {
+    #[serde(default)]
+    pub absorption: [f32; 3],
+    #[serde(default)]
+    pub scattering: [f32; 3],
+    #[serde(default)]
+    pub anisotropy: f32,
+    /// Index into the scene's density grids. The coefficients are scaled by
+    /// the grid's density, which makes the medium heterogeneous. Only used
+    /// for media inside objects, not for fog. Paths taking more than 256
+    /// tentative collisions to get through the grid are absorbed, which
+    /// darkens media whose peak density times size reaches the hundreds.
+    #[serde(default)]
+    pub density_grid: Option<usize>,
+}
+
+impl Medium {
+    pub fn new(absorption: [f32; 3], scattering: [f32; 3], anisotropy: f32) -> Medium {
+        Medium {
+            absorption,
+            scattering,
+            anisotropy,
+            density_grid: None,
+        }
+    }
+
+    pub fn with_density_grid(self, density_grid: usize) -> Medium {
+        Medium {
+            density_grid: Some(density_grid),
+            ..self
+        }
+    }
+
+    /// Grey medium that only scatters, like thin smoke or steam.
+    pub fn smoke(density: f32) -> Medium {
+        Medium::new([0.0; 3], [density; 3], 0.0)
+    }
+
+    /// Mostly absorbing medium, like soot.
+    pub fn soot(density: f32) -> Medium {
+        Medium::new([0.9 * density; 3], [0.1 * density; 3], 0.0)
+    }
+
+    /// Forward scattering haze for atmospheric shots.
+    pub fn fog(density: f32) -> Medium {
+        Medium::new([0.01 * density; 3], [density; 3], 0.7)
+    }
+
+    pub fn into_storage(self) -> MediumStorage {
+        MediumStorage {
+            absorption: self.absorption,
+            anisotropy: self.anisotropy,
+            scattering: self.scattering,
+            grid: self.density_grid.map_or(-1, |g| g as i32),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct MediumStorage {
+    absorption: [f32; 3],
+    anisotropy: f32,
+    scattering: [f32; 3],
+    grid: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Camera, DensityGrid, Medium, Object, RenderOptions, Scene, Shape};
+    use crate::gpu_state::testing::{mean, render};
+
+    const SIZE: u32 = 16;
+
+    fn scene() -> Scene {
+        Scene::new(Camera::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]))
+    }
+
+    /// Mean of the 4x4 pixels in the middle of the image.
+    fn center_mean(radiance: &[[f32; 3]]) -> f32 {
+        let center = SIZE as usize / 2;
+        let mut sum = 0.0;
+        for y in center - 2..center + 2 {
+            for x in center - 2..center + 2 {
+                sum += radiance[y * SIZE as usize + x].iter().sum::<f32>() / 3.0;
+            }
+        }
+        sum / 16.0
+    }
+
+    fn slab(medium: Medium) -> Object {
+        Object::volume(Shape::Cuboid { min: [1.0, -50.0, -50.0], max: [2.0, 50.0, 50.0] }, medium)
+    }
+
+    #[test]
+    fn scattering_conserves_energy() {
+        let mut scene = scene();
+        scene.add(Object::volume(Shape::Sphere { center: [3.0, 0.0, 0.0], radius: 1.0 }, Medium::smoke(0.5))).unwrap();
+        let Some(radiance) = render(SIZE, &scene, &RenderOptions::default(), 64) else { return };
+
+        let mean = mean(&radiance);
+        assert!((0.98..=1.005).contains(&mean), "white smoke in a white furnace should vanish: {mean}");
+    }
+
+    #[test]
+    fn heterogeneous_scattering_conserves_energy() {
+        let mut scene = scene();
+        let grid = scene.add_grid(DensityGrid::from_fn([8, 8, 8], |[x, y, z]| x * y + z).unwrap());
+        let cloud = Medium::smoke(0.5).with_density_grid(grid);
+        scene.add(Object::volume(Shape::Sphere { center: [3.0, 0.0, 0.0], radius: 1.0 }, cloud)).unwrap();
+        let Some(radiance) = render(SIZE, &scene, &RenderOptions::default(), 64) else { return };
+
+        let mean = mean(&radiance);
+        assert!((0.98..=1.005).contains(&mean), "white cloud in a white furnace should vanish: {mean}");
+    }
+
+    #[test]
+    fn absorption_follows_beer_lambert() {
+        let mut scene = scene();
+        scene.add(slab(Medium::new([1.0; 3], [0.0; 3], 0.0))).unwrap();
+        let Some(radiance) = render(SIZE, &scene, &RenderOptions::default(), 256) else { return };
+
+        let mean = center_mean(&radiance);
+        assert!((mean - (-1.0f32).exp()).abs() < 0.03, "transmittance through one unit of absorber: {mean}");
+    }
+
+    #[test]
+    fn ratio_tracking_matches_beer_lambert() {
+        let mut scene = scene();
+        // The denser corner raises the majorant so there are null collisions
+        // along the rays through the middle
+        let grid = scene.add_grid(DensityGrid::from_fn([4, 4, 4], |[_, y, z]| if y < 0.25 && z < 0.25 { 1.0 } else { 0.5 }).unwrap());
+        scene.add(slab(Medium::new([2.0; 3], [0.0; 3], 0.0).with_density_grid(grid))).unwrap();
+        let Some(radiance) = render(SIZE, &scene, &RenderOptions::default(), 256) else { return };
+
+        let mean = center_mean(&radiance);
+        assert!((mean - (-1.0f32).exp()).abs() < 0.02, "transmittance through one unit of absorber: {mean}");
+    }
+}
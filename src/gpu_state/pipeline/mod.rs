@@ -1,21 +1,48 @@
 mod vertex;
-mod camera;
-mod sphere;
+pub mod camera;
+pub mod material;
+pub mod medium;
+pub mod grid;
+pub mod scene;
+pub mod animation;
+pub mod display;
+pub mod aov;
+pub mod post;
+mod object;
+mod world;
+mod frame;
+mod denoiser;
+pub mod filter;
+pub mod sampler;
+mod reprojection;
 
 use wgpu::{
     RenderPipeline, Buffer, ShaderSource, VertexState, ColorTargetState, 
     BlendState, ShaderModuleDescriptor, PipelineLayoutDescriptor, 
     RenderPipelineDescriptor, FragmentState, ColorWrites, PrimitiveState, 
     PrimitiveTopology, FrontFace, Face, PolygonMode, MultisampleState, Device, 
-    SurfaceConfiguration, CommandEncoder, TextureView, RenderPassDescriptor, 
+    TextureFormat, CommandEncoder, TextureView, RenderPassDescriptor, 
     RenderPassColorAttachment, Operations, LoadOp, Color, ComputePipeline,
-    ComputePipelineDescriptor, BindGroup, ComputePassDescriptor,
+    ComputePipelineDescriptor, BindGroup, ComputePassDescriptor, Queue,
+    BufferDescriptor, BufferUsages, CommandEncoderDescriptor, MapMode, Maintain,
     util::{BufferInitDescriptor, DeviceExt},
 };
+use anyhow::Result;
+use bytemuck::Zeroable;
 use winit::dpi::PhysicalSize;
 use vertex::Vertex;
-use camera::Camera;
-use sphere::SphereStorage;
+use camera::{Camera, CameraUniform};
+use scene::Scene;
+use frame::FrameUniform;
+use display::{Display, DisplayUniform};
+use aov::{Aov, AOV_COUNT, AOV_FORMAT, AOV_TEXEL_SIZE};
+use denoiser::Denoiser;
+use reprojection::{Reprojection, MAX_HISTORY};
+use filter::{PixelFilter, Splatter};
+use sampler::{Sampler, BLUE_NOISE_SIZE};
+use post::{PostChain, PostEffects, PostUniform};
+use super::options::RenderOptions;
+use super::export::RadianceImage;
 
 const RECTANGLE_VERTICES: &[Vertex] = &[
     Vertex::new([ 1.0,  1.0], [1.0, 0.0]),
@@ -28,21 +55,85 @@ const RECTANGLE_VERTICES: &[Vertex] = &[
 
 const NUM_VERTICES: u32 = 6;
 
+pub const ACCUMULATION_TEXEL_SIZE: wgpu::BufferAddress = 16;
+
+/// Format of the texture the tracer writes the mean radiance to. Half floats
+/// keep everything brighter than white for the display pass.
+pub const RADIANCE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+const RADIANCE_TEXEL_SIZE: u32 = 8;
+
 pub struct Pipeline {
     size: wgpu::Extent3d,
     camera: Camera,
+    camera_uniform: CameraUniform,
+    scene: Scene,
+    // Scene time in seconds
+    time: f32,
     camera_buffer: Buffer,
-    objects_buffer: Buffer,
+    frame: u32,
+    // Accumulation pauses once this many frames have been traced
+    sample_limit: Option<u32>,
+    // Set by `update` once the limit is reached
+    paused: bool,
+    max_bounces: u32,
+    seed: u32,
+    sampler: Sampler,
+    frame_buffer: Buffer,
+    accumulation_buffer: Buffer,
+    pixel_filter: PixelFilter,
+    splatter: Splatter,
+    // One layer per AOV
+    aov_texture: wgpu::Texture,
+    // Shown instead of the image if set
+    shown_aov: Option<Aov>,
     vertex_buffer: Buffer,
     camera_bind_group: BindGroup,
     compute_bind_group: BindGroup,
     compute_pipeline: ComputePipeline,
+    display: Display,
+    display_uniform: DisplayUniform,
+    // Set when the surface does not apply the sRGB transfer function itself
+    encode_srgb: bool,
+    display_buffer: Buffer,
     render_bind_group: BindGroup,
+    denoiser: Denoiser,
+    // Shows the denoiser's output instead of the radiance texture
+    denoise: bool,
+    denoised_bind_group: BindGroup,
+    reprojection: Reprojection,
+    post_chain: PostChain,
+    post_effects: PostEffects,
+    post_uniform: PostUniform,
+    // Shows the output of the post effects when any of them is on
+    post_bind_group: BindGroup,
+    // Carries the image over to the new view when the camera moves
+    temporal: bool,
+    // Camera of the image in the accumulation buffer, `None` once it was
+    // reset
+    history_camera: Option<CameraUniform>,
+    // Set by `update` when the frame about to be traced starts a new view
+    // the history is reprojected into
+    reproject: bool,
+    // Whether the accumulated image of the current view holds reprojected
+    // history, and whether the frame about to be traced takes it out again
+    reprojected: bool,
+    expire_history: bool,
+    // Objects move with the time, so images from other times can not be
+    // reprojected
+    animated: bool,
     render_pipeline: RenderPipeline,
 }
 
 impl Pipeline {
-    pub fn new(device: &Device, config: &SurfaceConfiguration, size: PhysicalSize<u32>) -> Pipeline {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        size: PhysicalSize<u32>,
+        scene: &Scene,
+        options: &RenderOptions,
+    ) -> Result<Pipeline> {
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
             contents: bytemuck::cast_slice(RECTANGLE_VERTICES),
@@ -56,12 +147,12 @@ impl Pipeline {
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Texture"),
+            label: Some("Radiance Texture"),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format: RADIANCE_FORMAT,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -77,31 +168,116 @@ impl Pipeline {
             ..Default::default()
         });
 
-        let camera = Camera::default();
+        // Running sum of every sample traced since the camera last moved,
+        // the alpha channel holds the number of samples, or the sum of their
+        // weights when they are splatted.
+        let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * ACCUMULATION_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let aov_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("AOV Texture"),
+            size: wgpu::Extent3d {
+                depth_or_array_layers: AOV_COUNT,
+                ..size
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: AOV_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let aov_view = aov_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let camera = scene.camera;
+        let camera_uniform = camera.into_uniform(size.width as f32 / size.height as f32);
 
         let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer Descriptor"),
-            contents: bytemuck::cast_slice(&[camera.into_uniform()]),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
 
-        let spheres = &[
-            SphereStorage::new([10.0, 0.0, 0.0], [1.0, 0.0, 0.0], 1.0),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-            SphereStorage::new_random(),
-        ];
+        let frame_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Frame Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&[FrameUniform::new(0, options.max_bounces, 0.0, options.seed, false, options.sampler.id())]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
 
         let objects_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Objects Buffer Descriptor"),
-            contents: bytemuck::cast_slice(spheres),
+            contents: bytemuck::cast_slice(&scene.object_storage()?),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        let keyframes_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Keyframes Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&scene.keyframe_storage()),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        let world_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("World Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&[scene.world_uniform()]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let grid_atlas = scene.grid_atlas(device.limits().max_texture_dimension_3d)?;
+
+        let grid_texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+            label: Some("Density Grid Texture"),
+            size: grid_atlas.size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        }, bytemuck::cast_slice(&grid_atlas.densities));
+
+        let grid_view = grid_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let grids_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Grids Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&grid_atlas.grids),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
         });
 
+        // Tiled over the image by the blue noise sampler, the others ignore it
+        let blue_noise_size = if options.sampler == Sampler::BlueNoise { BLUE_NOISE_SIZE } else { 1 };
+        let blue_noise = if options.sampler == Sampler::BlueNoise { sampler::blue_noise() } else { vec![0.5] };
+        let blue_noise_texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+            label: Some("Blue Noise Texture"),
+            size: wgpu::Extent3d {
+                width: blue_noise_size,
+                height: blue_noise_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        }, bytemuck::cast_slice(&blue_noise));
+
+        let blue_noise_view = blue_noise_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let encode_srgb = !format.is_srgb();
+        let display_uniform = options.display.into_uniform(encode_srgb, None);
+        let display_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Display Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&[display_uniform]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Shader"),
             source: ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
@@ -120,15 +296,46 @@ impl Pipeline {
                         visibility: wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::StorageTexture {
                             access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba8Unorm,
+                            format: RADIANCE_FORMAT,
                             view_dimension: wgpu::TextureViewDimension::D2,
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: AOV_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("compute_bind_group_layout"),
             });
 
+        let splatter = Splatter::new(device, size, &accumulation_buffer, &view);
         let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &compute_bind_group_layout,
             entries: &[
@@ -136,6 +343,18 @@ impl Pipeline {
                     binding: 0,
                     resource: wgpu::BindingResource::TextureView(&view), // CHANGED!
                 },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&aov_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: splatter.sample_buffer().as_entire_binding(),
+                },
             ],
             label: Some("compute_bind_group"),
         });
@@ -163,7 +382,66 @@ impl Pipeline {
                         },
                         count: None,
                     },
-
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("camera_bind_group_layout"),
             });
@@ -178,7 +456,31 @@ impl Pipeline {
                 wgpu::BindGroupEntry {
                     binding: 1,
                     resource: objects_buffer.as_entire_binding(),
-                }
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: frame_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: world_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&grid_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: grids_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: keyframes_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&blue_noise_view),
+                },
             ],
             label: Some("camera_bind_group"),
         });
@@ -217,24 +519,61 @@ impl Pipeline {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
-        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let denoiser = Denoiser::new(device, size, &view, &aov_view);
+        let denoised_view = denoiser.output().create_view(&wgpu::TextureViewDescriptor::default());
+        let post_chain = PostChain::new(device, size, &[&view, &denoised_view]);
+        let post_view = post_chain.output().create_view(&wgpu::TextureViewDescriptor::default());
+        let reprojection = Reprojection::new(device, size, &camera_buffer, &aov_view, &accumulation_buffer, &view);
+
+        let render_bind_group = |view| device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &render_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view), // CHANGED!
+                    resource: wgpu::BindingResource::TextureView(view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&sampler), // CHANGED!
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: display_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&aov_view),
+                },
             ],
             label: Some("render_bind_group"),
         });
+        let denoised_bind_group = render_bind_group(&denoised_view);
+        let post_bind_group = render_bind_group(&post_view);
+        let render_bind_group = render_bind_group(&view);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&PipelineLayoutDescriptor {
@@ -258,7 +597,7 @@ impl Pipeline {
                     entry_point: "fs_main",
                     targets: &[Some(ColorTargetState {
                         // 4.
-                        format: config.format,
+                        format,
                         blend: Some(BlendState::REPLACE),
                         write_mask: ColorWrites::ALL,
                     })],
@@ -284,21 +623,57 @@ impl Pipeline {
                 multiview: None, // 5.
             });
 
-        Pipeline {
+        Ok(Pipeline {
             size,
             camera,
+            camera_uniform,
+            scene: scene.clone(),
+            time: 0.0,
             vertex_buffer,
             camera_buffer,
-            objects_buffer,
+            frame: 0,
+            sample_limit: None,
+            paused: false,
+            max_bounces: options.max_bounces,
+            seed: options.seed,
+            sampler: options.sampler,
+            frame_buffer,
+            accumulation_buffer,
+            pixel_filter: options.pixel_filter,
+            splatter,
+            aov_texture,
+            shown_aov: None,
             camera_bind_group,
             compute_bind_group,
             compute_pipeline,
+            display: options.display,
+            display_uniform,
+            encode_srgb,
+            display_buffer,
             render_bind_group,
+            denoiser,
+            denoise: options.denoise,
+            denoised_bind_group,
+            reprojection,
+            post_chain,
+            post_effects: scene.post,
+            post_uniform: PostUniform::zeroed(),
+            post_bind_group,
+            temporal: options.temporal,
+            history_camera: None,
+            reproject: false,
+            reprojected: false,
+            expire_history: false,
+            animated: !scene.animations.is_empty(),
             render_pipeline,
-        }
+        })
     }
 
-    pub fn render<'a>(&'a self, encoder: &mut CommandEncoder, view: &TextureView) {
+    pub fn trace(&self, encoder: &mut CommandEncoder) {
+        if self.reproject {
+            self.reprojection.save_history(encoder, &self.accumulation_buffer, &self.aov_texture);
+        }
+
         {
             let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
                 label: Some("Compute Pass"),
@@ -310,11 +685,51 @@ impl Pipeline {
             compute_pass.dispatch_workgroups(self.size.width, self.size.height, 1);
         }
 
+        if self.pixel_filter.splats() {
+            self.splatter.run(encoder);
+        }
+        if self.reproject {
+            self.reprojection.run(encoder);
+        }
+        if self.expire_history {
+            self.reprojection.expire(encoder);
+        }
+    }
+
+    /// Runs the denoiser and the post effects that are on over the
+    /// accumulated image, returning the texture holding the result or
+    /// `None` if the radiance texture is shown as it is.
+    fn finish(&self, encoder: &mut CommandEncoder) -> Option<&wgpu::Texture> {
+        if self.denoise {
+            self.denoiser.run(encoder);
+        }
+        if self.post_effects.any() {
+            self.post_chain.run(encoder, self.denoise as usize, self.post_effects.bloom.enabled);
+            Some(self.post_chain.output())
+        } else if self.denoise {
+            Some(self.denoiser.output())
+        } else {
+            None
+        }
+    }
+
+    /// Traces the next frame, unless the sample limit has been reached, and
+    /// draws the accumulated image to `view`.
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        if !self.paused {
+            self.trace(encoder);
+        }
+        self.finish(encoder);
+        self.draw(encoder, view);
+    }
+
+    /// Exposes, tone maps and encodes the finished image into `view`.
+    fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView) {
         {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: view,
+                    view,
                     resolve_target: None,
                     ops: Operations {
                         load: LoadOp::Clear(Color {
@@ -330,7 +745,14 @@ impl Pipeline {
             });
 
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+            let bind_group = if self.post_effects.any() {
+                &self.post_bind_group
+            } else if self.denoise {
+                &self.denoised_bind_group
+            } else {
+                &self.render_bind_group
+            };
+            render_pass.set_bind_group(0, bind_group, &[]);
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.draw(0..NUM_VERTICES, 0..1);
         }
@@ -340,7 +762,314 @@ impl Pipeline {
         &mut self.camera
     }
 
-    pub fn update_camera(&mut self, queue: &wgpu::Queue) {
-        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera.into_uniform()]));
+    /// Moves the focal plane onto the surface seen at `screen`, given in
+    /// normalised `[0, 1]` window coordinates.
+    pub fn focus_at(&mut self, screen: [f32; 2]) {
+        let size = [self.size.width as f32, self.size.height as f32];
+        let pixel = [screen[0] * size[0], screen[1] * size[1]];
+        let Some((origin, direction)) = self.camera.primary_ray(pixel, size) else { return };
+
+        if let Some(distance) = self.scene.raycast(origin, direction, self.time) {
+            self.camera.focus_along(direction, distance);
+        }
+    }
+
+    /// Starts accumulating from scratch on the next frame, even if the
+    /// camera has not moved. Nothing is reprojected into it.
+    pub fn reset_accumulation(&mut self) {
+        self.frame = 0;
+        self.history_camera = None;
+    }
+
+    /// Shows animated objects as they are `time` seconds into the scene.
+    pub fn set_time(&mut self, time: f32) {
+        if time != self.time {
+            self.time = time;
+            self.frame = 0;
+            if self.animated {
+                self.history_camera = None;
+            }
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Samples per pixel accumulated so far.
+    pub fn samples(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn display(&self) -> Display {
+        self.display
+    }
+
+    /// Changes exposure and tone mapping, unlike the camera this keeps the
+    /// accumulated image.
+    pub fn set_display(&mut self, display: Display) {
+        self.display = display;
+    }
+
+    pub fn denoise(&self) -> bool {
+        self.denoise
+    }
+
+    /// Turns the denoiser on or off, it only filters what is shown and read
+    /// back so the accumulated samples stay as they are.
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
     }
+
+    pub fn post_effects(&self) -> PostEffects {
+        self.post_effects
+    }
+
+    /// Changes the post effects, like the display this keeps the
+    /// accumulated image.
+    pub fn set_post_effects(&mut self, post_effects: PostEffects) {
+        self.post_effects = post_effects;
+    }
+
+    pub fn shown_aov(&self) -> Option<Aov> {
+        self.shown_aov
+    }
+
+    /// Shows `aov` instead of the image, or the image again for `None`.
+    pub fn set_shown_aov(&mut self, aov: Option<Aov>) {
+        self.shown_aov = aov;
+    }
+
+    pub fn temporal(&self) -> bool {
+        self.temporal
+    }
+
+    /// Turns temporal reprojection on or off. When on, moving the camera or
+    /// the scene time carries the image over to the new view, weighted as a
+    /// few samples, instead of starting from a single sample.
+    pub fn set_temporal(&mut self, temporal: bool) {
+        self.temporal = temporal;
+    }
+
+    pub fn set_sample_limit(&mut self, sample_limit: Option<u32>) {
+        self.sample_limit = sample_limit;
+    }
+
+    /// Copies the accumulated image back from the GPU, denoised and with
+    /// the post effects applied if they are on, waiting for all submitted
+    /// work to finish.
+    pub fn read_radiance(&self, device: &Device, queue: &Queue) -> RadianceImage {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Finish Encoder"),
+        });
+        let pixels = if let Some(texture) = self.finish(&mut encoder) {
+            queue.submit([encoder.finish()]);
+
+            let texels = read_texture(device, queue, texture, self.size, RADIANCE_TEXEL_SIZE);
+            bytemuck::cast_slice::<u8, [half::f16; 4]>(&texels[0])
+                .iter()
+                .map(|&[r, g, b, _]| [r.to_f32(), g.to_f32(), b.to_f32()])
+                .collect()
+        } else {
+            self.read_accumulation(device, queue)
+        };
+
+        RadianceImage {
+            width: self.size.width,
+            height: self.size.height,
+            pixels,
+            aovs: Vec::new(),
+        }
+    }
+
+    fn read_accumulation(&self, device: &Device, queue: &Queue) -> Vec<[f32; 3]> {
+        // Splatted samples with negative weights can cancel out
+        bytemuck::cast_slice::<u8, [f32; 4]>(&read_buffer(device, queue, &self.accumulation_buffer))
+            .iter()
+            .map(|&[r, g, b, n]| if n == 0.0 { [0.0; 3] } else { [r / n, g / n, b / n] })
+            .collect()
+    }
+
+    /// Copies the radiance and position in the pixel of the samples of the
+    /// last frame back from the GPU, if they were splatted.
+    #[cfg(test)]
+    pub fn read_samples(&self, device: &Device, queue: &Queue) -> Vec<([f32; 3], [f32; 2])> {
+        bytemuck::cast_slice::<u8, [f32; 8]>(&read_buffer(device, queue, self.splatter.sample_buffer()))
+            .iter()
+            .map(|&[r, g, b, _, x, y, _, _]| ([r, g, b], [x, y]))
+            .collect()
+    }
+
+    /// Draws the accumulated image the way it is shown on screen into a
+    /// texture of `format`, the format the pipeline was made for, and copies
+    /// its pixels back from the GPU.
+    #[cfg(test)]
+    pub fn read_display(&self, device: &Device, queue: &Queue, format: TextureFormat) -> Vec<u8> {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Display Readback Texture"),
+            size: self.size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // Display settings are otherwise only uploaded before tracing
+        let display_uniform = self.display.into_uniform(self.encode_srgb, self.shown_aov);
+        queue.write_buffer(&self.display_buffer, 0, bytemuck::cast_slice(&[display_uniform]));
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Display Encoder"),
+        });
+        self.finish(&mut encoder);
+        self.draw(&mut encoder, &texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        queue.submit([encoder.finish()]);
+
+        let texel_size = format.block_size(None).expect("display formats are colour formats");
+        read_texture(device, queue, &texture, self.size, texel_size).remove(0)
+    }
+
+    /// Copies the AOVs back from the GPU, in the order of `Aov::ALL`.
+    pub fn read_aovs(&self, device: &Device, queue: &Queue) -> Vec<Vec<[f32; 4]>> {
+        let size = wgpu::Extent3d {
+            depth_or_array_layers: AOV_COUNT,
+            ..self.size
+        };
+        read_texture(device, queue, &self.aov_texture, size, AOV_TEXEL_SIZE)
+            .iter()
+            .map(|layer| bytemuck::cast_slice(layer).to_vec())
+            .collect()
+    }
+
+    /// Uploads the camera, the scene time and the index of the frame about
+    /// to be traced. Accumulation restarts whenever the camera has changed,
+    /// from the reprojected history if temporal reprojection is on.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let display_uniform = self.display.into_uniform(self.encode_srgb, self.shown_aov);
+        if display_uniform != self.display_uniform {
+            self.display_uniform = display_uniform;
+            queue.write_buffer(&self.display_buffer, 0, bytemuck::cast_slice(&[display_uniform]));
+        }
+
+        let aspect = self.size.width as f32 / self.size.height as f32;
+        let camera_uniform = self.camera.into_uniform(aspect);
+        if camera_uniform != self.camera_uniform {
+            self.camera_uniform = camera_uniform;
+            self.frame = 0;
+            queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+        }
+
+        // The grain changes with every sample
+        let half_extent = camera_uniform
+            .perspective_half_extent()
+            .unwrap_or([aspect, 1.0].map(|x| x / (aspect * aspect + 1.0).sqrt()));
+        let post_uniform = self.post_effects.into_uniform(half_extent, self.frame);
+        if post_uniform != self.post_uniform {
+            self.post_uniform = post_uniform;
+            self.post_chain.write_uniform(queue, post_uniform);
+        }
+
+        self.paused = self.sample_limit.is_some_and(|limit| self.frame >= limit);
+        self.reproject = false;
+        self.expire_history = false;
+        // Samples in the image after this frame
+        self.denoiser.set_samples(queue, if self.paused { self.frame } else { self.frame + 1 });
+        if self.paused {
+            return;
+        }
+        if let Some(history_camera) = self.history_camera.filter(|_| self.temporal && self.frame == 0) {
+            self.reprojection.set_previous_camera(queue, history_camera);
+            self.reproject = true;
+        }
+        if self.frame == 0 {
+            self.reprojected = self.reproject;
+        }
+        // The view has more samples of its own than the history counts as
+        // once this frame is traced
+        self.expire_history = self.reprojected && self.frame == MAX_HISTORY;
+        let splat = self.pixel_filter.splats();
+        if splat {
+            self.splatter.write_uniform(queue, self.pixel_filter.into_uniform(self.frame));
+        }
+        let frame = FrameUniform::new(self.frame, self.max_bounces, self.time, self.seed, splat, self.sampler.id());
+        queue.write_buffer(&self.frame_buffer, 0, bytemuck::cast_slice(&[frame]));
+        self.frame += 1;
+        self.history_camera = Some(self.camera_uniform);
+    }
+}
+
+/// Copies all of `buffer` back from the GPU.
+fn read_buffer(device: &Device, queue: &Queue, buffer: &Buffer) -> Vec<u8> {
+    let staging_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: buffer.size(),
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+    queue.submit([encoder.finish()]);
+
+    let slice = staging_buffer.slice(..);
+    slice.map_async(MapMode::Read, |result| result.unwrap());
+    device.poll(Maintain::Wait);
+
+    let bytes = slice.get_mapped_range().to_vec();
+    staging_buffer.unmap();
+    bytes
+}
+
+/// Copies every layer of `texture` back from the GPU, each as tightly packed
+/// rows of `texel_size` bytes.
+fn read_texture(device: &Device, queue: &Queue, texture: &wgpu::Texture, size: wgpu::Extent3d, texel_size: u32) -> Vec<Vec<u8>> {
+    let row_size = size.width * texel_size;
+    // Rows of texture copies are aligned, the padding is dropped below
+    let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let layer_size = (padded_row_size * size.height) as wgpu::BufferAddress;
+
+    let staging_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: layer_size * size.depth_or_array_layers as wgpu::BufferAddress,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &staging_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_size),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = staging_buffer.slice(..);
+    slice.map_async(MapMode::Read, |result| result.unwrap());
+    device.poll(Maintain::Wait);
+
+    let layers = slice
+        .get_mapped_range()
+        .chunks(layer_size as usize)
+        .map(|layer| {
+            layer
+                .chunks(padded_row_size as usize)
+                .flat_map(|row| &row[..row_size as usize])
+                .copied()
+                .collect()
+        })
+        .collect();
+    staging_buffer.unmap();
+
+    layers
 }
@@ -0,0 +1,68 @@
+use bytemuck::{Pod, Zeroable};
+use super::material::MaterialStorage;
+use super::medium::MediumStorage;
+
+pub const SHAPE_SPHERE: u32 = 0;
+pub const SHAPE_CUBOID: u32 = 1;
+
+pub const HAS_SURFACE: u32 = 1;
+pub const HAS_MEDIUM: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ObjectStorage {
+    center: [f32; 3],
+    shape: u32,
+    // Radius of a sphere in the first component, half extents of a cuboid
+    size: [f32; 3],
+    flags: u32,
+    material: MaterialStorage,
+    medium: MediumStorage,
+    // First keyframe of the object's animation and how many it has, zero
+    // for objects that do not move
+    keyframes: u32,
+    keyframe_count: u32,
+    // Index among the scene's distinct materials, for the id AOV
+    material_id: u32,
+    _padding: u32,
+}
+
+impl ObjectStorage {
+    pub fn new(
+        shape: u32,
+        center: [f32; 3],
+        size: [f32; 3],
+        flags: u32,
+        material: MaterialStorage,
+        medium: MediumStorage,
+    ) -> ObjectStorage {
+        ObjectStorage {
+            center,
+            shape,
+            size,
+            flags,
+            material,
+            medium,
+            keyframes: 0,
+            keyframe_count: 0,
+            material_id: 0,
+
+            _padding: 0,
+        }
+    }
+
+    pub fn with_keyframes(self, keyframes: u32, keyframe_count: u32) -> ObjectStorage {
+        ObjectStorage {
+            keyframes,
+            keyframe_count,
+            ..self
+        }
+    }
+
+    pub fn with_material_id(self, material_id: u32) -> ObjectStorage {
+        ObjectStorage {
+            material_id,
+            ..self
+        }
+    }
+}
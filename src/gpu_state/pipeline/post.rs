@@ -0,0 +1,505 @@
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, ComputePass, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, Extent3d, PipelineLayoutDescriptor, Queue, Sampler, ShaderModuleDescriptor,
+    ShaderSource, Texture, TextureView,
+};
+use super::RADIANCE_FORMAT;
+
+/// Halvings of the resolution the bloom is spread over, the widest glare
+/// reaches about a sixteenth of the image.
+const BLOOM_LEVELS: u32 = 6;
+
+// Pixels per workgroup along each axis
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Light scattered by the lens and the film into a wide halo around bright
+/// sources. Unlike a threshold bloom it redistributes a fixed share of all
+/// light, so only sources far brighter than their surroundings stand out.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bloom {
+    pub enabled: bool,
+    /// Share of the light scattered into the halo.
+    pub intensity: f32,
+    /// Weight of each wider level of the halo against the narrower one in
+    /// `[0, 1]`, higher values reach further.
+    pub spread: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom {
+            enabled: true,
+            intensity: 0.04,
+            spread: 0.75,
+        }
+    }
+}
+
+/// Natural vignetting, the cos⁴ falloff of light reaching the film at an
+/// angle. Projections other than perspective darken as if their corners
+/// were seen at 45°.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Vignette {
+    pub enabled: bool,
+    /// Scales the exponent of the falloff, `1` is the cos⁴ law.
+    pub strength: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Vignette {
+        Vignette {
+            enabled: true,
+            strength: 1.0,
+        }
+    }
+}
+
+/// Radial lens distortion and lateral chromatic aberration, after Brown's
+/// model with a single coefficient.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lens {
+    pub enabled: bool,
+    /// Positive values bulge the image outwards like a wide angle lens,
+    /// negative ones pinch it. The image is scaled so that nothing beyond
+    /// its edges comes into view.
+    pub distortion: f32,
+    /// Difference of the distortion between the red and green, and green
+    /// and blue channels, fringing edges towards the corners.
+    pub chromatic_aberration: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Lens {
+        Lens {
+            enabled: true,
+            distortion: 0.05,
+            chromatic_aberration: 0.01,
+        }
+    }
+}
+
+/// Monochrome film grain, noise multiplying the radiance that changes with
+/// every sample.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grain {
+    pub enabled: bool,
+    /// Standard deviation of the noise relative to the radiance.
+    pub strength: f32,
+}
+
+impl Default for Grain {
+    fn default() -> Grain {
+        Grain {
+            enabled: true,
+            strength: 0.05,
+        }
+    }
+}
+
+/// Lens and film effects applied to the radiance before it is exposed and
+/// tone mapped, in the viewer and in saved images alike. Scene files set
+/// them in a `[post]` table, an effect with a table of its own is on unless
+/// it says `enabled = false`:
+///
+/// ```toml
+/// [post.bloom]
+/// intensity = 0.05
+///
+/// [post.vignette]
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostEffects {
+    pub bloom: Bloom,
+    pub vignette: Vignette,
+    pub lens: Lens,
+    pub grain: Grain,
+}
+
+impl PostEffects {
+    /// Whether any effect is on, otherwise the chain is skipped.
+    pub fn any(&self) -> bool {
+        self.bloom.enabled || self.vignette.enabled || self.lens.enabled || self.grain.enabled
+    }
+
+    /// `half_extent` is the tangent of half the field of view, the vignette
+    /// falls off with it. `seed` picks the grain.
+    pub fn into_uniform(self, half_extent: [f32; 2], seed: u32) -> PostUniform {
+        PostUniform {
+            half_extent,
+            bloom_intensity: if self.bloom.enabled { self.bloom.intensity } else { 0.0 },
+            bloom_spread: self.bloom.spread,
+            vignette: if self.vignette.enabled { self.vignette.strength } else { 0.0 },
+            distortion: if self.lens.enabled { self.lens.distortion } else { 0.0 },
+            chromatic_aberration: if self.lens.enabled { self.lens.chromatic_aberration } else { 0.0 },
+            grain: if self.grain.enabled { self.grain.strength } else { 0.0 },
+            seed,
+            _padding: [0; 3],
+        }
+    }
+}
+
+impl Default for PostEffects {
+    fn default() -> PostEffects {
+        PostEffects {
+            bloom: Bloom {
+                enabled: false,
+                ..Bloom::default()
+            },
+            vignette: Vignette {
+                enabled: false,
+                ..Vignette::default()
+            },
+            lens: Lens {
+                enabled: false,
+                ..Lens::default()
+            },
+            grain: Grain {
+                enabled: false,
+                ..Grain::default()
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct PostUniform {
+    half_extent: [f32; 2],
+    bloom_intensity: f32,
+    bloom_spread: f32,
+    vignette: f32,
+    distortion: f32,
+    chromatic_aberration: f32,
+    grain: f32,
+    seed: u32,
+    _padding: [u32; 3],
+}
+
+// Bind groups reading one of the chain's inputs
+struct SourceBindGroups {
+    downsample: BindGroup,
+    composite: BindGroup,
+}
+
+/// Runs the post effects on the tracer's or the denoiser's output. Bloom
+/// halves the image level by level and adds the levels back up, then a
+/// composite pass distorts, blends in the bloom, darkens the corners and
+/// adds grain.
+pub struct PostChain {
+    size: Extent3d,
+    uniform_buffer: Buffer,
+    downsample_pipeline: ComputePipeline,
+    upsample_pipeline: ComputePipeline,
+    composite_pipeline: ComputePipeline,
+    sources: Vec<SourceBindGroups>,
+    // Level `i` downsampled from level `i - 1`, then from the level below
+    downsample_passes: Vec<BindGroup>,
+    // Levels from the second smallest up to the first, each blending the
+    // level below into its own
+    upsample_passes: Vec<BindGroup>,
+    level_sizes: Vec<Extent3d>,
+    output: Texture,
+}
+
+impl PostChain {
+    /// Builds the chain for every texture in `sources`, `run` picks one of
+    /// them by index.
+    pub fn new(device: &Device, size: Extent3d, sources: &[&TextureView]) -> PostChain {
+        let texture = |label, size| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: RADIANCE_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let view = |texture: &Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let output = texture("Post Texture", size);
+        let output_view = view(&output);
+
+        // Level 0 is the source itself
+        let level_sizes: Vec<Extent3d> = (1..=BLOOM_LEVELS)
+            .map(|level| Extent3d {
+                width: (size.width >> level).max(1),
+                height: (size.height >> level).max(1),
+                depth_or_array_layers: 1,
+            })
+            .collect();
+        let down_views: Vec<TextureView> = level_sizes.iter().map(|&size| view(&texture("Bloom Down Texture", size))).collect();
+        let up_views: Vec<TextureView> = level_sizes.iter().map(|&size| view(&texture("Bloom Up Texture", size))).collect();
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Buffer"),
+            size: std::mem::size_of::<PostUniform>() as wgpu::BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+
+        let input = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                input(0),
+                input(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: RADIANCE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+
+        let bind_group = |first: &TextureView, second: &TextureView, output: &TextureView| {
+            bind_group(device, &bind_group_layout, [first, second, output], &sampler, &uniform_buffer)
+        };
+
+        let sources = sources
+            .iter()
+            .map(|source| SourceBindGroups {
+                downsample: bind_group(source, source, &down_views[0]),
+                composite: bind_group(source, &up_views[0], &output_view),
+            })
+            .collect();
+        let downsample_passes = (1..BLOOM_LEVELS as usize)
+            .map(|level| bind_group(&down_views[level - 1], &down_views[level - 1], &down_views[level]))
+            .collect();
+        // The smallest level is only downsampled, the ones above blend in
+        // the level below
+        let upsample_passes = (0..BLOOM_LEVELS as usize - 1)
+            .rev()
+            .map(|level| {
+                let below = if level + 2 == BLOOM_LEVELS as usize { &down_views[level + 1] } else { &up_views[level + 1] };
+                bind_group(&down_views[level], below, &up_views[level])
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Post Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        PostChain {
+            size,
+            uniform_buffer,
+            downsample_pipeline: pipeline("downsample"),
+            upsample_pipeline: pipeline("upsample"),
+            composite_pipeline: pipeline("composite"),
+            sources,
+            downsample_passes,
+            upsample_passes,
+            level_sizes,
+            output,
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &Queue, uniform: PostUniform) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Applies the effects to the source with index `source`, the bloom
+    /// levels are only computed if `bloom` is set.
+    pub fn run(&self, encoder: &mut CommandEncoder, source: usize, bloom: bool) {
+        let source = &self.sources[source];
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Post Pass"),
+        });
+        if bloom {
+            dispatch(&mut compute_pass, &self.downsample_pipeline, &source.downsample, self.level_sizes[0]);
+            for (bind_group, &size) in self.downsample_passes.iter().zip(&self.level_sizes[1..]) {
+                dispatch(&mut compute_pass, &self.downsample_pipeline, bind_group, size);
+            }
+            for (bind_group, &size) in self.upsample_passes.iter().zip(self.level_sizes.iter().rev().skip(1)) {
+                dispatch(&mut compute_pass, &self.upsample_pipeline, bind_group, size);
+            }
+        }
+        dispatch(&mut compute_pass, &self.composite_pipeline, &source.composite, self.size);
+    }
+
+    /// Texture the composite pass writes to.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+}
+
+fn dispatch<'a>(compute_pass: &mut ComputePass<'a>, pipeline: &'a ComputePipeline, bind_group: &'a BindGroup, size: Extent3d) {
+    compute_pass.set_pipeline(pipeline);
+    compute_pass.set_bind_group(0, bind_group, &[]);
+    compute_pass.dispatch_workgroups(size.width.div_ceil(WORKGROUP_SIZE), size.height.div_ceil(WORKGROUP_SIZE), 1);
+}
+
+fn bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    [first, second, output]: [&TextureView; 3],
+    sampler: &Sampler,
+    uniform_buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(first),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(second),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(output),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("post_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Camera, Material, Object, RenderOptions, Scene};
+    use crate::gpu_state::testing::{ball_on_ground, mean, render, rms_error};
+
+    const SIZE: u32 = 32;
+
+    #[test]
+    fn vignetting_follows_the_cosine_fourth_law() {
+        // Nothing but the white background
+        let mut scene = Scene::new(Camera::default());
+        scene.post.vignette.enabled = true;
+        let Some(image) = render(SIZE, &scene, &RenderOptions::default(), 1) else { return };
+
+        let [width, height] = Camera::default().into_uniform(1.0).perspective_half_extent().unwrap();
+        let corner = (1.0 - 1.0 / SIZE as f32) * width;
+        let tangent2 = corner * corner + ((1.0 - 1.0 / SIZE as f32) * height).powi(2);
+        let expected = (1.0 / (1.0 + tangent2)).powi(2);
+        assert!((image[0][0] - expected).abs() < 1e-2, "{} against {expected}", image[0][0]);
+        let center = image[(SIZE * SIZE / 2 + SIZE / 2) as usize][0];
+        assert!(center > 0.99, "{center}");
+    }
+
+    #[test]
+    fn bloom_spreads_light_around_bright_sources() {
+        let mut scene = Scene::new(Camera::new([-10.0, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        scene.background = [0.0; 3];
+        scene.add(Object::sphere([0.0, 0.0, 0.0], 0.2, Material::emissive([100.0; 3]))).unwrap();
+        let Some(plain) = render(SIZE, &scene, &RenderOptions::default(), 1) else { return };
+        scene.post.bloom.enabled = true;
+        let bloomed = render(SIZE, &scene, &RenderOptions::default(), 1).unwrap();
+
+        // A pixel a quarter of the image away from the source
+        let pixel = (SIZE * SIZE / 2 + SIZE / 4) as usize;
+        assert_eq!(plain[pixel], [0.0; 3]);
+        assert!(bloomed[pixel][0] > 0.0, "{:?}", bloomed[pixel]);
+
+        // Light is moved around, not added
+        let total = |image: &[[f32; 3]]| image.iter().map(|p| p[0]).sum::<f32>();
+        let ratio = total(&bloomed) / total(&plain);
+        assert!((ratio - 1.0).abs() < 0.1, "{ratio}");
+    }
+
+    #[test]
+    fn distortion_without_aberration_keeps_the_centre() {
+        // An odd size puts a pixel right on the optical axis
+        const ODD: u32 = 33;
+        let mut scene = ball_on_ground();
+        let Some(plain) = render(ODD, &scene, &RenderOptions::default(), 1) else { return };
+        scene.post.lens.enabled = true;
+        scene.post.lens.distortion = 0.3;
+        scene.post.lens.chromatic_aberration = 0.0;
+        let distorted = render(ODD, &scene, &RenderOptions::default(), 1).unwrap();
+
+        let center = (ODD * ODD / 2) as usize;
+        for channel in 0..3 {
+            assert!((distorted[center][channel] - plain[center][channel]).abs() < 1e-3, "{:?} became {:?}", plain[center], distorted[center]);
+        }
+        assert!(rms_error(&distorted, &plain) > 0.01, "the rest of the image is bent");
+    }
+
+    #[test]
+    fn grain_keeps_the_mean() {
+        let mut scene = Scene::new(Camera::default());
+        scene.post.grain.enabled = true;
+        let Some(image) = render(SIZE, &scene, &RenderOptions::default(), 1) else { return };
+
+        let average = mean(&image);
+        assert!((average - 1.0).abs() < 0.01, "{average}");
+        let deviation = (image.iter().map(|p| (p[0] - average).powi(2)).sum::<f32>() / image.len() as f32).sqrt();
+        assert!((deviation - scene.post.grain.strength).abs() < 0.02, "{deviation}");
+    }
+}
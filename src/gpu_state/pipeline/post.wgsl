@@ -0,0 +1,122 @@
+// Lens and film effects on linear radiance. `downsample` and `upsample`
+// build the bloom: a pyramid of ever smaller blurred copies of the image,
+// added back up level by level so bright sources get a halo that falls off
+// smoothly. `composite` samples the source and the bloom through the
+// distorted lens, then applies vignetting and grain.
+
+@group(0) @binding(0) var first: texture_2d<f32>;
+@group(0) @binding(1) var second: texture_2d<f32>;
+@group(0) @binding(2) var output: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(3) var linearSampler: sampler;
+@group(0) @binding(4) var<uniform> post: Post;
+
+struct Post {
+    halfExtent: vec2<f32>,
+    bloomIntensity: f32,
+    bloomSpread: f32,
+    vignette: f32,
+    distortion: f32,
+    chromaticAberration: f32,
+    grain: f32,
+    seed: u32,
+}
+
+// Halves `first` into `output`. Four bilinear taps a texel of `first` apart
+// average a 4 by 4 footprint, which keeps small highlights from flickering
+// in and out of the smaller levels.
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let uv: vec2<f32> = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let texel: vec2<f32> = 1.0 / vec2<f32>(textureDimensions(first));
+
+    var sum: vec3<f32> = vec3(0.0);
+    for (var y: i32 = -1; y <= 1; y += 2) {
+        for (var x: i32 = -1; x <= 1; x += 2) {
+            sum += textureSampleLevel(first, linearSampler, uv + vec2<f32>(vec2(x, y)) * texel, 0.0).rgb;
+        }
+    }
+    textureStore(output, vec2<i32>(id.xy), vec4(0.25 * sum, 1.0));
+}
+
+// Blends `second`, the level below, upsampled with a 3 by 3 tent into
+// `first`, the downsampled level of the same size as `output`.
+@compute @workgroup_size(8, 8, 1)
+fn upsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let uv: vec2<f32> = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let texel: vec2<f32> = 1.0 / vec2<f32>(textureDimensions(second));
+
+    var sum: vec3<f32> = vec3(0.0);
+    for (var y: i32 = -1; y <= 1; y++) {
+        for (var x: i32 = -1; x <= 1; x++) {
+            let weight: f32 = f32((2 - abs(x)) * (2 - abs(y))) / 16.0;
+            sum += weight * textureSampleLevel(second, linearSampler, uv + vec2<f32>(vec2(x, y)) * texel, 0.0).rgb;
+        }
+    }
+    let level: vec3<f32> = textureLoad(first, vec2<i32>(id.xy), 0).rgb;
+    textureStore(output, vec2<i32>(id.xy), vec4(mix(level, sum, post.bloomSpread), 1.0));
+}
+
+// `first` is the image and `second` the largest bloom level.
+@compute @workgroup_size(8, 8, 1)
+fn composite(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let uv: vec2<f32> = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+
+    // Distances from the centre are measured in units of the half diagonal
+    // so the corners sit at one whatever the aspect ratio
+    let aspect: f32 = f32(size.x) / f32(size.y);
+    let centred: vec2<f32> = (uv - 0.5) * vec2(aspect, 1.0);
+    let radius2: f32 = dot(centred, centred) / (0.25 * (aspect * aspect + 1.0));
+
+    // Each channel looks up where the lens bent its wavelength from, scaled
+    // so that nothing beyond the edges is sampled
+    let coefficients: vec3<f32> = post.distortion + vec3(1.0, 0.0, -1.0) * post.chromaticAberration;
+    let scales: vec3<f32> = (1.0 + coefficients * radius2) / (1.0 + max(coefficients, vec3(0.0)));
+    var color: vec3<f32>;
+    for (var channel: i32 = 0; channel < 3; channel++) {
+        let source: vec2<f32> = 0.5 + (uv - 0.5) * scales[channel];
+        var value: f32 = textureSampleLevel(first, linearSampler, source, 0.0)[channel];
+        if (post.bloomIntensity > 0.0) {
+            let glare: f32 = textureSampleLevel(second, linearSampler, source, 0.0)[channel];
+            value = mix(value, glare, post.bloomIntensity);
+        }
+        color[channel] = value;
+    }
+
+    // cos⁴ of the angle the light reaches the film at
+    let tangent: vec2<f32> = (2.0 * uv - 1.0) * post.halfExtent;
+    let cosine2: f32 = 1.0 / (1.0 + dot(tangent, tangent));
+    color *= pow(cosine2, 2.0 * post.vignette);
+
+    if (post.grain > 0.0) {
+        color *= max(1.0 + post.grain * gaussian(id.xy), 0.0);
+    }
+
+    textureStore(output, vec2<i32>(id.xy), vec4(color, 1.0));
+}
+
+// Standard normal noise, a fresh value per pixel and seed
+fn gaussian(pixel: vec2<u32>) -> f32 {
+    let a: u32 = pcgHash(pixel.x ^ pcgHash(pixel.y ^ pcgHash(post.seed)));
+    let b: u32 = pcgHash(a);
+    let u1: f32 = (f32(a >> 8u) + 1.0) / 16777217.0;
+    let u2: f32 = f32(b >> 8u) / 16777216.0;
+    return sqrt(-2.0 * log(u1)) * cos(6.2831853 * u2);
+}
+
+fn pcgHash(input: u32) -> u32 {
+    let state: u32 = input * 747796405u + 2891336453u;
+    let word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
@@ -1,15 +1,130 @@
-@group(0) @binding(0) var colorBuffer: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(0) var colorBuffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(1) var<storage, read_write> accumulation: array<vec4<f32>>;
+@group(0) @binding(2) var aovs: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(3) var<storage, read_write> samples: array<Sample>;
 @group(1) @binding(0) var<uniform> camera: Camera;
-@group(1) @binding(1) var<storage, read> objects: Spheres;
+@group(1) @binding(1) var<storage, read> objects: Objects;
+@group(1) @binding(2) var<uniform> frame: Frame;
+@group(1) @binding(3) var<uniform> world: World;
+@group(1) @binding(4) var densityAtlas: texture_3d<f32>;
+@group(1) @binding(5) var<storage, read> grids: Grids;
+@group(1) @binding(6) var<storage, read> keyframes: Keyframes;
+@group(1) @binding(7) var blueNoise: texture_2d<f32>;
 
-struct Sphere {
+const PI: f32 = 3.14159265358979;
+const SURFACE_OFFSET: f32 = 1e-4;
+const NO_HIT: f32 = 3.4e38;
+
+const PROJECTION_PERSPECTIVE: u32 = 0u;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1u;
+const PROJECTION_EQUIRECTANGULAR: u32 = 2u;
+const PROJECTION_FISHEYE_EQUIDISTANT: u32 = 3u;
+const PROJECTION_FISHEYE_EQUISOLID: u32 = 4u;
+const PROJECTION_CUBE_MAP: u32 = 5u;
+
+const STEREO_NONE: u32 = 0u;
+const STEREO_SIDE_BY_SIDE: u32 = 1u;
+const STEREO_OVER_UNDER: u32 = 2u;
+
+const SHAPE_SPHERE: u32 = 0u;
+const SHAPE_CUBOID: u32 = 1u;
+
+const HAS_SURFACE: u32 = 1u;
+const HAS_MEDIUM: u32 = 2u;
+
+// Index standing for the world's fog rather than an object's interior
+const WORLD_MEDIUM: i32 = -1;
+
+const PASSED: u32 = 0u;
+const SCATTERED: u32 = 1u;
+const ABSORBED: u32 = 2u;
+
+const AOV_DEPTH: i32 = 0;
+const AOV_NORMAL: i32 = 1;
+const AOV_ALBEDO: i32 = 2;
+const AOV_ID: i32 = 3;
+const AOV_POSITION: i32 = 4;
+
+// Upper bound on tentative collisions per segment through a density grid,
+// paths still inside after that many are absorbed
+const MAX_TRACKING_STEPS: u32 = 256u;
+// Upper bound on volume boundaries looked through for the AOVs
+const MAX_BOUNDARY_CROSSINGS: u32 = 16u;
+
+const SAMPLER_PCG: u32 = 0u;
+const SAMPLER_SOBOL: u32 = 1u;
+const SAMPLER_BLUE_NOISE: u32 = 2u;
+
+// Dimensions of the sampler set aside for the camera ray: pixel offset,
+// time and lens
+const CAMERA_DIMENSIONS: u32 = 8u;
+// Dimensions set aside for every vertex of a path, numbers drawn beyond them
+// come from PCG
+const VERTEX_DIMENSIONS: u32 = 8u;
+
+struct Material {
+    baseColor: vec3<f32>,
+    metallic: f32,
+    emission: vec3<f32>,
+    roughness: f32,
+    specular: f32,
+    specularTint: f32,
+    clearcoat: f32,
+    clearcoatRoughness: f32,
+    sheen: f32,
+    sheenTint: f32,
+    transmission: f32,
+    ior: f32,
+}
+
+struct Medium {
+    absorption: vec3<f32>,
+    anisotropy: f32,
+    scattering: vec3<f32>,
+    grid: i32,
+}
+
+struct Grid {
+    dimensions: vec3<u32>,
+    offset: u32,
+    maxDensity: f32,
+}
+
+struct Grids {
+    grids: array<Grid>,
+}
+
+struct Object {
     center: vec3<f32>,
-	color: vec3<f32>,
-    radius: f32,
+    shape: u32,
+    size: vec3<f32>,
+    flags: u32,
+    material: Material,
+    medium: Medium,
+    keyframes: u32,
+    keyframeCount: u32,
+    materialId: u32,
+}
+
+struct Objects {
+	objects: array<Object>,
+}
+
+struct TransformKey {
+    translation: vec3<f32>,
+    time: f32,
+    rotation: vec4<f32>,
+    scale: vec3<f32>,
+}
+
+struct Keyframes {
+    keys: array<TransformKey>,
 }
 
-struct Spheres {
-	spheres: array<Sphere>,
+struct Transform {
+    translation: vec3<f32>,
+    rotation: vec4<f32>,
+    scale: vec3<f32>,
 }
 
 struct Ray {
@@ -19,113 +134,514 @@ struct Ray {
 
 struct Camera {
     position: vec3<f32>,
+    aperture: f32,
 	forwards: vec3<f32>,
+    focusDistance: f32,
 	right: vec3<f32>,
+    blades: u32,
 	up: vec3<f32>,
+    projection: u32,
+    halfExtent: vec2<f32>,
+    eyeOffset: f32,
+    eyeConvergence: f32,
+    stereo: u32,
+    shutterOpen: f32,
+    shutterClose: f32,
+}
+
+struct Frame {
+    index: u32,
+    maxBounces: u32,
+    time: f32,
+    seed: u32,
+    splat: u32,
+    sampling: u32,
+}
+
+struct Sample {
+    radiance: vec3<f32>,
+    // Position inside the pixel, for the reconstruction filter
+    offset: vec2<f32>,
+}
+
+struct World {
+    background: vec3<f32>,
+    hasFog: u32,
+    fog: Medium,
 }
 
 struct RenderState {
 	t: f32,
-	color: vec3<f32>,
+	object: u32,
 	hit: bool,
+	frontFace: bool,
 	position: vec3<f32>,
 	normal: vec3<f32>,
 }
 
+struct Scatter {
+    direction: vec3<f32>,
+    weight: vec3<f32>,
+}
+
+// Outcome of flying through a medium up to the next surface
+struct Interaction {
+    kind: u32,
+    distance: f32,
+    weight: vec3<f32>,
+}
+
+var<private> rngState: u32;
+// Scrambles the sampler's sequence differently for every pixel
+var<private> pixelSeed: u32;
+var<private> pixelCoordinates: vec2<u32>;
+// Next dimension `random` draws from and the first one it may not use
+var<private> dimension: u32;
+var<private> dimensionEnd: u32;
+// Moment the current path is traced at, animated objects are placed where
+// they are at this time
+var<private> rayTime: f32;
+// First surface the camera ray hits, for the AOVs
+var<private> firstHit: RenderState;
+
 @compute @workgroup_size(1,1,1)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     let screenSize: vec2<u32> = textureDimensions(colorBuffer);
     let screenPos: vec2<i32> = vec2<i32>(id.xy);
+    let pixelIndex: u32 = id.y * screenSize.x + id.x;
+
+    rngState = pcgHash(pixelIndex ^ pcgHash(frame.index ^ pcgHash(frame.seed)));
+    pixelSeed = pcgHash(pixelIndex ^ pcgHash(frame.seed));
+    pixelCoordinates = id.xy;
+    startDimensions(0u, CAMERA_DIMENSIONS);
+
+    // Jitter inside the pixel so accumulated frames are antialiased
+    let offset: vec2<f32> = random2();
+    rayTime = frame.time + mix(camera.shutterOpen, camera.shutterClose, random());
+    var pixel: vec2<f32> = vec2<f32>(screenPos) + offset;
+
+    // Split the frame between the eyes, -1 is the left eye and 1 the right
+    var eyeSize: vec2<f32> = vec2<f32>(screenSize);
+    var eye: f32 = 0.0;
+    if (camera.stereo == STEREO_SIDE_BY_SIDE) {
+        eyeSize.x *= 0.5;
+        eye = select(-1.0, 1.0, pixel.x >= eyeSize.x);
+        pixel.x -= max(eye, 0.0) * eyeSize.x;
+    } else if (camera.stereo == STEREO_OVER_UNDER) {
+        eyeSize.y *= 0.5;
+        eye = select(-1.0, 1.0, pixel.y >= eyeSize.y);
+        pixel.y -= max(eye, 0.0) * eyeSize.y;
+    }
+    let eyeOffset: f32 = eye * camera.eyeOffset;
+
+    // Texture rows run top to bottom while the up vector points up
+    let uv: vec2<f32> = vec2(2.0 * pixel.x / eyeSize.x - 1.0, 1.0 - 2.0 * pixel.y / eyeSize.y);
+    let forwards: vec3<f32> = camera.forwards;
+    let right: vec3<f32> = camera.right;
+    let up: vec3<f32> = camera.up;
+
+    var pixelColor: vec3<f32> = vec3(0.0, 0.0, 0.0);
+    if (camera.projection == PROJECTION_EQUIRECTANGULAR) {
+        // Omni-directional stereo, the eyes sit on a circle around the camera
+        // and move together towards the poles
+        let longitude: f32 = uv.x * PI;
+        let latitude: f32 = uv.y * 0.5 * PI;
+        let direction: vec3<f32> = cos(latitude) * (cos(longitude) * forwards + sin(longitude) * right) + sin(latitude) * up;
+        let tangent: vec3<f32> = cos(longitude) * right - sin(longitude) * forwards;
+        let offset: f32 = eyeOffset * cos(latitude);
+
+        var myRay: Ray;
+        myRay.origin = camera.position + offset * tangent;
+        myRay.direction = normalize(direction - offset * camera.eyeConvergence * tangent);
+        pixelColor = rayColor(myRay);
+    } else if (camera.projection > PROJECTION_ORTHOGRAPHIC) {
+        // Panoramas are always rendered with a pinhole, `w` is zero for
+        // pixels outside of the image
+        let direction: vec4<f32> = panoramaDirection(uv, pixel / eyeSize);
+        if (direction.w > 0.0) {
+            var myRay: Ray;
+            myRay.origin = camera.position + eyeOffset * right;
+            myRay.direction = normalize(direction.xyz);
+            pixelColor = rayColor(myRay);
+        }
+    } else {
+        let x: f32 = uv.x * camera.halfExtent.x;
+        let y: f32 = uv.y * camera.halfExtent.y;
+        let eyePosition: vec3<f32> = camera.position + eyeOffset * right;
+
+        var pinhole: Ray;
+        if (camera.projection == PROJECTION_ORTHOGRAPHIC) {
+            pinhole.origin = eyePosition + x * right + y * up;
+            pinhole.direction = forwards;
+        } else {
+            // Shifting the image plane instead of turning the eyes keeps
+            // vertical lines parallel
+            pinhole.origin = eyePosition;
+            pinhole.direction = normalize(forwards + (x - eyeOffset * camera.eyeConvergence) * right + y * up);
+        }
+
+        // Thin lens, every ray through the lens meets the pinhole ray on the
+        // focal plane
+        let focusPoint: vec3<f32> = pinhole.origin + pinhole.direction * camera.focusDistance / dot(pinhole.direction, forwards);
+        let lens: vec2<f32> = camera.aperture * sampleAperture(camera.blades, vec3(random2(), random()));
+
+        var myRay: Ray;
+        myRay.origin = pinhole.origin + lens.x * right + lens.y * up;
+        myRay.direction = normalize(focusPoint - myRay.origin);
+
+        pixelColor = rayColor(myRay);
+    }
+
+    // Wider pixel filters spread the sample over its neighbours in a pass of
+    // their own
+    if (frame.splat != 0u) {
+        samples[pixelIndex] = Sample(pixelColor, offset);
+    } else {
+        var accumulated: vec4<f32> = vec4<f32>(pixelColor, 1.0);
+        if (frame.index > 0u) {
+            accumulated += accumulation[pixelIndex];
+        }
+        accumulation[pixelIndex] = accumulated;
+
+        textureStore(colorBuffer, screenPos, vec4<f32>(accumulated.rgb / accumulated.a, 1.0));
+    }
+
+    // The AOVs keep the first sample, so they hold still while accumulating
+    if (frame.index == 0u) {
+        storeAovs(screenPos);
+    }
+}
+
+fn storeAovs(screenPos: vec2<i32>) {
+    var depth: f32 = NO_HIT;
+    var normal: vec3<f32> = vec3(0.0);
+    var albedo: vec3<f32> = min(world.background, vec3(1.0));
+    var ids: vec2<f32> = vec2(-1.0);
+    var position: vec3<f32> = vec3(0.0);
+
+    if (firstHit.hit) {
+        let object: Object = objects.objects[firstHit.object];
+        let offset: vec3<f32> = firstHit.position - camera.position;
+        depth = select(dot(offset, camera.forwards), length(offset), camera.projection > PROJECTION_ORTHOGRAPHIC);
+        normal = firstHit.normal;
+        albedo = object.material.baseColor;
+        ids = vec2(f32(firstHit.object), f32(object.materialId));
+        position = firstHit.position;
+    }
+
+    textureStore(aovs, screenPos, AOV_DEPTH, vec4(depth, 0.0, 0.0, 0.0));
+    textureStore(aovs, screenPos, AOV_NORMAL, vec4(normal, 0.0));
+    textureStore(aovs, screenPos, AOV_ALBEDO, vec4(albedo, 0.0));
+    textureStore(aovs, screenPos, AOV_ID, vec4(ids, 0.0, 0.0));
+    textureStore(aovs, screenPos, AOV_POSITION, vec4(position, 0.0));
+}
 
-    let horizontalCoefficient: f32 = (f32(screenPos.x) - f32(screenSize.x) / 2.0) / f32(screenSize.x);
-    let verticalCoefficient: f32 = (f32(screenPos.y) - f32(screenSize.y) / 2.0) / f32(screenSize.x);
+// Direction of the fisheye or cube map ray through `uv` in [-1, 1], with `st`
+// the same position in [0, 1] measured from the top left corner
+fn panoramaDirection(uv: vec2<f32>, st: vec2<f32>) -> vec4<f32> {
     let forwards: vec3<f32> = camera.forwards;
     let right: vec3<f32> = camera.right;
     let up: vec3<f32> = camera.up;
 
-    var myRay: Ray;
-    myRay.direction = normalize(forwards + horizontalCoefficient * right + verticalCoefficient * up);
-    myRay.origin = camera.position;
+    if (camera.projection == PROJECTION_CUBE_MAP) {
+        // Faces in a 3 by 2 grid: right, left, up / down, front, back
+        let cell: vec2<f32> = min(st * vec2(3.0, 2.0), vec2(2.999, 1.999));
+        let face: u32 = u32(cell.y) * 3u + u32(cell.x);
+        let u: f32 = 2.0 * fract(cell.x) - 1.0;
+        let v: f32 = 1.0 - 2.0 * fract(cell.y);
 
-    var pixelColor: vec3<f32> = rayColor(myRay);
+        var axes: array<vec3<f32>, 3>;
+        switch face {
+            case 0u: { axes = array(right, -forwards, up); }
+            case 1u: { axes = array(-right, forwards, up); }
+            case 2u: { axes = array(up, right, -forwards); }
+            case 3u: { axes = array(-up, right, forwards); }
+            case 4u: { axes = array(forwards, right, up); }
+            default: { axes = array(-forwards, -right, up); }
+        }
+        return vec4(axes[0] + u * axes[1] + v * axes[2], 1.0);
+    }
 
-    textureStore(colorBuffer, screenPos, vec4<f32>(pixelColor, 1.0));
+    // Fisheye, the half field of view is stored in the half extent
+    let radius: f32 = length(uv);
+    if (radius > 1.0) {
+        return vec4(0.0, 0.0, 0.0, 0.0);
+    }
+    var angle: f32 = radius * camera.halfExtent.x;
+    if (camera.projection == PROJECTION_FISHEYE_EQUISOLID) {
+        angle = 2.0 * asin(radius * sin(0.5 * camera.halfExtent.x));
+    }
+    var offset: vec2<f32> = vec2(0.0, 0.0);
+    if (radius > 0.0) {
+        offset = uv / radius;
+    }
+    return vec4(cos(angle) * forwards + sin(angle) * (offset.x * right + offset.y * up), 1.0);
 }
 
 fn rayColor(ray: Ray) -> vec3<f32> {
+    if (frame.index == 0u) {
+        firstHit = firstSurface(ray);
+    }
 
-    var color: vec3<f32> = vec3(1.0, 1.0, 1.0);
+    var radiance: vec3<f32> = vec3(0.0, 0.0, 0.0);
+    var throughput: vec3<f32> = vec3(1.0, 1.0, 1.0);
     var result: RenderState;
+    var medium: i32 = mediumAt(ray.origin);
 
     var temp_ray: Ray;
     temp_ray.origin = ray.origin;
     temp_ray.direction = ray.direction;
 
-    let bounces: u32 = 4u;
-    for(var bounce: u32 = 0u; bounce < bounces; bounce++) {
+    // Crossing the invisible boundary of a volume is not a bounce, but the
+    // loop still needs an upper bound
+    var bounce: u32 = 0u;
+    for(var step: u32 = 0u; step < 4u * frame.maxBounces && bounce < frame.maxBounces; step++) {
+        startDimensions(CAMERA_DIMENSIONS + step * VERTEX_DIMENSIONS, VERTEX_DIMENSIONS);
 
         result = trace(temp_ray);
+        let tHit: f32 = select(NO_HIT, result.t, result.hit);
+
+        // Free flight through the medium the ray is travelling in
+        if (medium != WORLD_MEDIUM || world.hasFog != 0u) {
+            var participating: Medium = world.fog;
+            var interaction: Interaction;
+
+            if (medium == WORLD_MEDIUM) {
+                interaction = homogeneousFreeFlight(participating, tHit);
+            } else {
+                let volume: Object = objects.objects[medium];
+                participating = volume.medium;
+                if (participating.grid >= 0) {
+                    interaction = heterogeneousFreeFlight(volume, temp_ray, tHit);
+                } else {
+                    interaction = homogeneousFreeFlight(participating, tHit);
+                }
+            }
 
-        //unpack color
-        color = color * result.color;
+            throughput *= interaction.weight;
+
+            if (interaction.kind == ABSORBED) {
+                break;
+            }
+
+            if (interaction.kind == SCATTERED) {
+                temp_ray.origin = temp_ray.origin + interaction.distance * temp_ray.direction;
+                temp_ray.direction = sampleHenyeyGreenstein(temp_ray.direction, participating.anisotropy, random2());
+                bounce++;
+                continue;
+            }
+        }
 
         //early exit
         if (!result.hit) {
+            radiance += throughput * world.background;
             break;
         }
 
-        //Set up for next trace
-        temp_ray.origin = result.position;
-        temp_ray.direction = normalize(reflect(temp_ray.direction, result.normal));
+        let object: Object = objects.objects[result.object];
+
+        // Invisible boundary of a volume, carry on in the same direction
+        if ((object.flags & HAS_SURFACE) == 0u) {
+            medium = select(WORLD_MEDIUM, i32(result.object), result.frontFace);
+            temp_ray.origin = result.position - SURFACE_OFFSET * result.normal;
+            continue;
+        }
+
+        radiance += throughput * object.material.emission;
+
+        let scatter: Scatter = sampleBsdf(result, object.material, -temp_ray.direction);
+        throughput *= scatter.weight;
+        bounce++;
+
+        if (all(throughput == vec3(0.0, 0.0, 0.0))) {
+            break;
+        }
+
+        //Set up for next trace, nudged off the surface on the side the ray leaves from
+        let transmitted: bool = dot(scatter.direction, result.normal) < 0.0;
+        let offset: f32 = select(SURFACE_OFFSET, -SURFACE_OFFSET, transmitted);
+        temp_ray.origin = result.position + offset * result.normal;
+        temp_ray.direction = scatter.direction;
+
+        if (transmitted && (object.flags & HAS_MEDIUM) != 0u) {
+            medium = select(WORLD_MEDIUM, i32(result.object), result.frontFace);
+        }
+    }
+
+    //Rays which still hit something after the last bounce carry no light
+    return radiance;
+}
+
+// First surface along the camera ray for the AOVs, looking through the
+// boundaries of volumes. Media are ignored, so the AOVs of a pixel don't
+// depend on where its first sample happened to scatter.
+fn firstSurface(ray: Ray) -> RenderState {
+    var probe: Ray = ray;
+    var result: RenderState;
+    for (var crossing: u32 = 0u; crossing < MAX_BOUNDARY_CROSSINGS; crossing++) {
+        result = trace(probe);
+        if (!result.hit || (objects.objects[result.object].flags & HAS_SURFACE) != 0u) {
+            return result;
+        }
+        probe.origin = result.position - SURFACE_OFFSET * result.normal;
     }
+    result.hit = false;
+    return result;
+}
 
-    //Rays which reached terminal state and bounced indefinitely
-    if (result.hit) {
-        color = vec3(0.0, 0.0, 0.0);
+// Medium of the last object in the list containing the point
+fn mediumAt(position: vec3<f32>) -> i32 {
+    var medium: i32 = WORLD_MEDIUM;
+
+	for (var i: u32 = 0u; i < arrayLength(&objects.objects); i++) {
+        let object: Object = objects.objects[i];
+        if ((object.flags & HAS_MEDIUM) != 0u && inside(position, object)) {
+            medium = i32(i);
+        }
     }
 
-    return color;
+    return medium;
+}
+
+fn inside(position: vec3<f32>, object: Object) -> bool {
+    let offset: vec3<f32> = restPosition(object, position) - object.center;
+    if (object.shape == SHAPE_CUBOID) {
+        return all(abs(offset) < object.size);
+    }
+    return dot(offset, offset) < object.size.x * object.size.x;
 }
 
 fn trace(ray: Ray) -> RenderState {
     var renderState: RenderState;
-	renderState.color = vec3(1.0, 1.0, 1.0);
 
     var nearestHit: f32 = 9999.0;
-    
-	for (var i: u32 = 0u; i < arrayLength(&objects.spheres); i++) {
-        
-        var newRenderState: RenderState = hit(ray, objects.spheres[i], 0.001, nearestHit, renderState);
+
+	for (var i: u32 = 0u; i < arrayLength(&objects.objects); i++) {
+
+        let object: Object = objects.objects[i];
+
+        // Animated objects are hit in their untransformed shape, the ray's
+        // direction is not normalised there so distances stay the same
+        var localRay: Ray = ray;
+        var transform: Transform;
+        if (object.keyframeCount > 0u) {
+            transform = objectTransform(object);
+            localRay.origin = object.center + toRest(transform, ray.origin - object.center - transform.translation);
+            localRay.direction = toRest(transform, ray.direction);
+        }
+
+        var newRenderState: RenderState;
+        if (object.shape == SHAPE_CUBOID) {
+            newRenderState = hitCuboid(localRay, object, 0.001, nearestHit);
+        } else {
+            newRenderState = hitSphere(localRay, object, 0.001, nearestHit);
+        }
+
+        if (newRenderState.hit && object.keyframeCount > 0u) {
+            // Normals transform with the inverse scale, which keeps the side
+            // they face unchanged
+            newRenderState.position = ray.origin + newRenderState.t * ray.direction;
+            newRenderState.normal = normalize(rotate(transform.rotation, newRenderState.normal / transform.scale));
+        }
 
         if (newRenderState.hit) {
             nearestHit = newRenderState.t;
             renderState = newRenderState;
+            renderState.object = i;
         }
     }
 
     return renderState;
 }
 
-fn hit(ray: Ray, sphere: Sphere, tMin: f32, tMax: f32, oldRenderState: RenderState) -> RenderState {
-    
+// Transform of an animated object at the time of the current path, with
+// linear translation and scale and slerped rotation between keyframes
+fn objectTransform(object: Object) -> Transform {
+    let first: TransformKey = keyframes.keys[object.keyframes];
+    var start: TransformKey = first;
+    var end: TransformKey = first;
+    for (var i: u32 = 1u; i < object.keyframeCount; i++) {
+        end = keyframes.keys[object.keyframes + i];
+        if (end.time > rayTime) {
+            break;
+        }
+        start = end;
+    }
+
+    var s: f32 = 0.0;
+    if (end.time > start.time) {
+        s = clamp((rayTime - start.time) / (end.time - start.time), 0.0, 1.0);
+    }
+
+    var transform: Transform;
+    transform.translation = mix(start.translation, end.translation, s);
+    transform.rotation = slerp(start.rotation, end.rotation, s);
+    transform.scale = mix(start.scale, end.scale, s);
+    return transform;
+}
+
+// Where a point of an object lies in its untransformed shape
+fn restPosition(object: Object, position: vec3<f32>) -> vec3<f32> {
+    if (object.keyframeCount == 0u) {
+        return position;
+    }
+    let transform: Transform = objectTransform(object);
+    return object.center + toRest(transform, position - object.center - transform.translation);
+}
+
+// Undoes the rotation and scale of a transform
+fn toRest(transform: Transform, v: vec3<f32>) -> vec3<f32> {
+    let inverse: vec4<f32> = vec4(-transform.rotation.xyz, transform.rotation.w);
+    return rotate(inverse, v) / transform.scale;
+}
+
+// Rotation by a unit quaternion stored as (x, y, z, w)
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+fn slerp(a: vec4<f32>, b: vec4<f32>, s: f32) -> vec4<f32> {
+    // Take the short way round
+    var cosAngle: f32 = dot(a, b);
+    var end: vec4<f32> = b;
+    if (cosAngle < 0.0) {
+        end = -b;
+        cosAngle = -cosAngle;
+    }
+    if (cosAngle > 0.9995) {
+        return normalize(mix(a, end, s));
+    }
+    let angle: f32 = acos(cosAngle);
+    return normalize(sin((1.0 - s) * angle) * a + sin(s * angle) * end);
+}
+
+fn hitSphere(ray: Ray, sphere: Object, tMin: f32, tMax: f32) -> RenderState {
+
+    let radius: f32 = sphere.size.x;
     let co: vec3<f32> = ray.origin - sphere.center;
     let a: f32 = dot(ray.direction, ray.direction);
-    let b: f32 = 2.0 * dot(ray.direction, co);
-    let c: f32 = dot(co, co) - sphere.radius * sphere.radius;
-    let discriminant: f32 = b * b - 4.0 * a * c;
+    let halfB: f32 = dot(ray.direction, co);
+    // Distance from the centre to the ray's closest approach, this keeps the
+    // discriminant accurate for grazing rays
+    let closest: vec3<f32> = co - (halfB / a) * ray.direction;
+    let discriminant: f32 = a * (radius * radius - dot(closest, closest));
 
     var renderState: RenderState;
-    renderState.color = oldRenderState.color;
 
     if (discriminant > 0.0) {
 
-        let t: f32 = (-b - sqrt(discriminant)) / (2.0 * a);
+        // Rays starting inside the sphere (refraction) hit the far side
+        var t: f32 = (-halfB - sqrt(discriminant)) / a;
+        if (t <= tMin) {
+            t = (-halfB + sqrt(discriminant)) / a;
+        }
 
         if (t > tMin && t < tMax) {
 			renderState.position = ray.origin + t*ray.direction;
-			renderState.normal = normalize(renderState.position - sphere.center);
+			let outwardNormal: vec3<f32> = (renderState.position - sphere.center) / radius;
+            renderState.frontFace = dot(ray.direction, outwardNormal) < 0.0;
+			renderState.normal = select(-outwardNormal, outwardNormal, renderState.frontFace);
             renderState.t = t;
-            renderState.color = sphere.color;
             renderState.hit = true;
             return renderState;
         }
@@ -133,5 +649,456 @@ fn hit(ray: Ray, sphere: Sphere, tMin: f32, tMax: f32, oldRenderState: RenderSta
 
     renderState.hit = false;
     return renderState;
-    
+
+}
+
+fn hitCuboid(ray: Ray, cuboid: Object, tMin: f32, tMax: f32) -> RenderState {
+
+    // Slab test against the three pairs of faces
+    let inverseDirection: vec3<f32> = 1.0 / ray.direction;
+    let t0: vec3<f32> = (cuboid.center - cuboid.size - ray.origin) * inverseDirection;
+    let t1: vec3<f32> = (cuboid.center + cuboid.size - ray.origin) * inverseDirection;
+    let tNear: vec3<f32> = min(t0, t1);
+    let tFar: vec3<f32> = max(t0, t1);
+    let entry: f32 = max(max(tNear.x, tNear.y), tNear.z);
+    let exit: f32 = min(min(tFar.x, tFar.y), tFar.z);
+
+    var renderState: RenderState;
+
+    if (entry <= exit) {
+
+        // Rays starting inside the cuboid hit the far side
+        var t: f32 = entry;
+        if (t <= tMin) {
+            t = exit;
+        }
+
+        if (t > tMin && t < tMax) {
+			renderState.position = ray.origin + t*ray.direction;
+
+            // The face hit is the one the point sticks out of the most
+            let local: vec3<f32> = (renderState.position - cuboid.center) / cuboid.size;
+            let distance: vec3<f32> = abs(local);
+            var outwardNormal: vec3<f32> = vec3(sign(local.x), 0.0, 0.0);
+            if (distance.y >= distance.x && distance.y >= distance.z) {
+                outwardNormal = vec3(0.0, sign(local.y), 0.0);
+            } else if (distance.z >= distance.x) {
+                outwardNormal = vec3(0.0, 0.0, sign(local.z));
+            }
+
+            renderState.frontFace = dot(ray.direction, outwardNormal) < 0.0;
+			renderState.normal = select(-outwardNormal, outwardNormal, renderState.frontFace);
+            renderState.t = t;
+            renderState.hit = true;
+            return renderState;
+        }
+    }
+
+    renderState.hit = false;
+    return renderState;
+
+}
+
+// Distances are sampled with the mean extinction and the colour difference
+// is folded into the weight
+fn homogeneousFreeFlight(medium: Medium, tHit: f32) -> Interaction {
+    var interaction: Interaction;
+    interaction.kind = PASSED;
+    interaction.weight = vec3(1.0, 1.0, 1.0);
+
+    let extinction: vec3<f32> = medium.absorption + medium.scattering;
+    let meanExtinction: f32 = (extinction.r + extinction.g + extinction.b) / 3.0;
+
+    if (meanExtinction <= 0.0) {
+        return interaction;
+    }
+
+    let distance: f32 = -log(1.0 - random()) / meanExtinction;
+
+    if (distance < tHit) {
+        let transmittance: vec3<f32> = exp(-extinction * distance);
+        interaction.kind = SCATTERED;
+        interaction.distance = distance;
+        interaction.weight = medium.scattering * transmittance / (meanExtinction * exp(-meanExtinction * distance));
+    } else {
+        interaction.weight = exp(-extinction * tHit) / exp(-meanExtinction * tHit);
+    }
+
+    return interaction;
+}
+
+// Delta tracking against the grid's maximum density. Null collisions are
+// picked by their mean probability and weighted per channel, so coloured
+// media stay unbiased. Pure absorbers only need the transmittance, which
+// ratio tracking estimates without killing any paths.
+fn heterogeneousFreeFlight(volume: Object, ray: Ray, tHit: f32) -> Interaction {
+    let medium: Medium = volume.medium;
+    let grid: Grid = grids.grids[medium.grid];
+
+    var interaction: Interaction;
+    interaction.kind = PASSED;
+    interaction.weight = vec3(1.0, 1.0, 1.0);
+
+    let extinction: vec3<f32> = medium.absorption + medium.scattering;
+    let majorant: f32 = grid.maxDensity * max(max(extinction.r, extinction.g), extinction.b);
+    let ratioTracking: bool = all(medium.scattering == vec3(0.0, 0.0, 0.0));
+
+    if (majorant <= 0.0) {
+        return interaction;
+    }
+
+    var distance: f32 = 0.0;
+    for (var step: u32 = 0u; step < MAX_TRACKING_STEPS; step++) {
+        distance -= log(1.0 - random()) / majorant;
+        if (distance >= tHit) {
+            return interaction;
+        }
+
+        let density: f32 = gridDensity(grid, volume, ray.origin + distance * ray.direction);
+        let absorption: vec3<f32> = density * medium.absorption;
+        let scattering: vec3<f32> = density * medium.scattering;
+        let nullCollision: vec3<f32> = majorant - absorption - scattering;
+
+        if (ratioTracking) {
+            interaction.weight *= nullCollision / majorant;
+            continue;
+        }
+
+        let scatterProbability: f32 = (scattering.r + scattering.g + scattering.b) / (3.0 * majorant);
+        let nullProbability: f32 = (nullCollision.r + nullCollision.g + nullCollision.b) / (3.0 * majorant);
+        let event: f32 = random();
+
+        if (event < scatterProbability) {
+            interaction.kind = SCATTERED;
+            interaction.distance = distance;
+            interaction.weight *= scattering / (majorant * scatterProbability);
+            return interaction;
+        } else if (event < scatterProbability + nullProbability) {
+            interaction.weight *= nullCollision / (majorant * nullProbability);
+        } else {
+            interaction.kind = ABSORBED;
+            interaction.weight = vec3(0.0, 0.0, 0.0);
+            return interaction;
+        }
+    }
+
+    // Out of steps. Absorbing the path darkens segments with a majorant
+    // optical depth of a few hundred, letting it pass would brighten them
+    // instead, and a dense medium looks far less wrong too dark than glowing.
+    interaction.kind = ABSORBED;
+    interaction.weight = vec3(0.0, 0.0, 0.0);
+    return interaction;
+}
+
+// Trilinear lookup of a grid stretched over the bounding box of its object
+fn gridDensity(grid: Grid, volume: Object, position: vec3<f32>) -> f32 {
+    let uvw: vec3<f32> = (restPosition(volume, position) - volume.center + volume.size) / (2.0 * volume.size);
+    if (any(uvw < vec3(0.0, 0.0, 0.0)) || any(uvw > vec3(1.0, 1.0, 1.0))) {
+        return 0.0;
+    }
+
+    let last: vec3<f32> = vec3<f32>(grid.dimensions - 1u);
+    let voxel: vec3<f32> = clamp(uvw * vec3<f32>(grid.dimensions) - 0.5, vec3(0.0, 0.0, 0.0), last);
+    let lower: vec3<f32> = floor(voxel);
+    let fraction: vec3<f32> = voxel - lower;
+    let i0: vec3<i32> = vec3<i32>(lower) + vec3(0, 0, i32(grid.offset));
+    let i1: vec3<i32> = vec3<i32>(min(lower + 1.0, last)) + vec3(0, 0, i32(grid.offset));
+
+    let c000: f32 = textureLoad(densityAtlas, vec3(i0.x, i0.y, i0.z), 0).r;
+    let c100: f32 = textureLoad(densityAtlas, vec3(i1.x, i0.y, i0.z), 0).r;
+    let c010: f32 = textureLoad(densityAtlas, vec3(i0.x, i1.y, i0.z), 0).r;
+    let c110: f32 = textureLoad(densityAtlas, vec3(i1.x, i1.y, i0.z), 0).r;
+    let c001: f32 = textureLoad(densityAtlas, vec3(i0.x, i0.y, i1.z), 0).r;
+    let c101: f32 = textureLoad(densityAtlas, vec3(i1.x, i0.y, i1.z), 0).r;
+    let c011: f32 = textureLoad(densityAtlas, vec3(i0.x, i1.y, i1.z), 0).r;
+    let c111: f32 = textureLoad(densityAtlas, vec3(i1.x, i1.y, i1.z), 0).r;
+
+    let c00: f32 = mix(c000, c100, fraction.x);
+    let c10: f32 = mix(c010, c110, fraction.x);
+    let c01: f32 = mix(c001, c101, fraction.x);
+    let c11: f32 = mix(c011, c111, fraction.x);
+    return mix(mix(c00, c10, fraction.y), mix(c01, c11, fraction.y), fraction.z);
+}
+
+// Henyey–Greenstein phase function, sampled around the direction of travel
+fn sampleHenyeyGreenstein(direction: vec3<f32>, g: f32, u: vec2<f32>) -> vec3<f32> {
+    var cosTheta: f32 = 1.0 - 2.0 * u.x;
+    if (abs(g) > 1e-3) {
+        let term: f32 = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+        cosTheta = (1.0 + g * g - term * term) / (2.0 * g);
+    }
+
+    let sinTheta: f32 = sqrt(max(0.0, 1.0 - cosTheta * cosTheta));
+    let phi: f32 = 2.0 * PI * u.y;
+    let tangent: vec3<f32> = orthonormalTangent(direction);
+    let bitangent: vec3<f32> = cross(direction, tangent);
+    return toWorld(vec3(sinTheta * cos(phi), sinTheta * sin(phi), cosTheta), tangent, bitangent, direction);
+}
+
+// Principled BSDF
+//
+// Every lobe is importance sampled on its own and picked with a probability
+// equal to its share of the energy, so the returned weight never exceeds the
+// reflectance of the chosen lobe and the material can not create energy.
+
+fn sampleBsdf(state: RenderState, material: Material, worldOut: vec3<f32>) -> Scatter {
+    let normal: vec3<f32> = state.normal;
+    let tangent: vec3<f32> = orthonormalTangent(normal);
+    let bitangent: vec3<f32> = cross(normal, tangent);
+
+    let wo: vec3<f32> = vec3(dot(worldOut, tangent), dot(worldOut, bitangent), max(dot(worldOut, normal), 1e-4));
+
+    var scatter: Scatter;
+    var wi: vec3<f32>;
+
+    let coat: f32 = material.clearcoat * schlickWeight(wo.z) * 0.96 + material.clearcoat * 0.04;
+    let lobe: f32 = random();
+    let metallic: f32 = material.metallic;
+    let transmission: f32 = (1.0 - metallic) * material.transmission;
+
+    if (state.frontFace && random() < coat) {
+        // Clearcoat, a colourless dielectric layer on top of everything else
+        let alpha: f32 = roughnessToAlpha(mix(0.001, 0.3, material.clearcoatRoughness));
+        let microfacet: vec3<f32> = sampleGgxVndf(wo, alpha, random2());
+        wi = reflect(-wo, microfacet);
+        scatter.weight = vec3(1.0, 1.0, 1.0) * smithG1(wi, alpha);
+    } else if (lobe < metallic) {
+        // Conductor
+        let alpha: f32 = roughnessToAlpha(material.roughness);
+        let microfacet: vec3<f32> = sampleGgxVndf(wo, alpha, random2());
+        wi = reflect(-wo, microfacet);
+        scatter.weight = schlickFresnel(material.baseColor, dot(wo, microfacet)) * smithG1(wi, alpha);
+    } else if (lobe < metallic + transmission) {
+        // Rough dielectric, reflects or refracts depending on the Fresnel term
+        let alpha: f32 = roughnessToAlpha(material.roughness);
+        let microfacet: vec3<f32> = sampleGgxVndf(wo, alpha, random2());
+        let eta: f32 = select(material.ior, 1.0 / material.ior, state.frontFace);
+        let fresnel: f32 = dielectricFresnel(dot(wo, microfacet), eta);
+        let refracted: vec3<f32> = refract(-wo, microfacet, eta);
+
+        if (random() < fresnel || all(refracted == vec3(0.0, 0.0, 0.0))) {
+            wi = reflect(-wo, microfacet);
+            scatter.weight = vec3(1.0, 1.0, 1.0) * smithG1(wi, alpha);
+        } else {
+            wi = refracted;
+            scatter.weight = material.baseColor * smithG1(wi, alpha);
+            scatter.direction = toWorld(wi, tangent, bitangent, normal);
+            return scatter;
+        }
+    } else {
+        // Dielectric specular on top of a diffuse base
+        let f0: vec3<f32> = min(0.08 * material.specular * mix(vec3(1.0, 1.0, 1.0), tint(material.baseColor), material.specularTint), vec3(1.0, 1.0, 1.0));
+        let viewFresnel: vec3<f32> = schlickFresnel(f0, wo.z);
+        let specularProbability: f32 = (viewFresnel.r + viewFresnel.g + viewFresnel.b) / 3.0;
+
+        if (random() < specularProbability) {
+            let alpha: f32 = roughnessToAlpha(material.roughness);
+            let microfacet: vec3<f32> = sampleGgxVndf(wo, alpha, random2());
+            wi = reflect(-wo, microfacet);
+            scatter.weight = schlickFresnel(f0, dot(wo, microfacet)) * smithG1(wi, alpha) / specularProbability;
+        } else {
+            wi = sampleCosineHemisphere(random2());
+            let halfway: vec3<f32> = normalize(wi + wo);
+            let sheenColor: vec3<f32> = mix(vec3(1.0, 1.0, 1.0), tint(material.baseColor), material.sheenTint);
+            let diffuseColor: vec3<f32> = mix(material.baseColor, sheenColor, material.sheen * schlickWeight(dot(wi, halfway)));
+            scatter.weight = (1.0 - viewFresnel) * diffuseColor / (1.0 - specularProbability);
+        }
+    }
+
+    // Reflections pointing below the surface are absorbed
+    if (wi.z <= 0.0) {
+        scatter.weight = vec3(0.0, 0.0, 0.0);
+    }
+
+    scatter.direction = toWorld(wi, tangent, bitangent, normal);
+    return scatter;
+}
+
+// Visible normal sampling of the GGX distribution (Heitz 2018)
+fn sampleGgxVndf(wo: vec3<f32>, alpha: f32, u: vec2<f32>) -> vec3<f32> {
+    let vh: vec3<f32> = normalize(vec3(alpha * wo.x, alpha * wo.y, wo.z));
+
+    let lengthSquared: f32 = vh.x * vh.x + vh.y * vh.y;
+    var t1: vec3<f32> = vec3(1.0, 0.0, 0.0);
+    if (lengthSquared > 0.0) {
+        t1 = vec3(-vh.y, vh.x, 0.0) / sqrt(lengthSquared);
+    }
+    let t2: vec3<f32> = cross(vh, t1);
+
+    let r: f32 = sqrt(u.x);
+    let phi: f32 = 2.0 * PI * u.y;
+    let p1: f32 = r * cos(phi);
+    let s: f32 = 0.5 * (1.0 + vh.z);
+    let p2: f32 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+
+    let nh: vec3<f32> = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+    return normalize(vec3(alpha * nh.x, alpha * nh.y, max(1e-6, nh.z)));
+}
+
+fn smithG1(w: vec3<f32>, alpha: f32) -> f32 {
+    let cosSquared: f32 = w.z * w.z;
+    let tanSquared: f32 = max(0.0, 1.0 - cosSquared) / max(cosSquared, 1e-7);
+    return 2.0 / (1.0 + sqrt(1.0 + alpha * alpha * tanSquared));
+}
+
+fn roughnessToAlpha(roughness: f32) -> f32 {
+    return max(roughness * roughness, 1e-3);
+}
+
+fn schlickWeight(cosTheta: f32) -> f32 {
+    let m: f32 = clamp(1.0 - cosTheta, 0.0, 1.0);
+    return m * m * m * m * m;
+}
+
+fn schlickFresnel(f0: vec3<f32>, cosTheta: f32) -> vec3<f32> {
+    return mix(f0, vec3(1.0, 1.0, 1.0), schlickWeight(cosTheta));
+}
+
+// Unpolarised Fresnel reflectance, eta is the ratio of the incident to the
+// transmitted index of refraction
+fn dielectricFresnel(cosIncident: f32, eta: f32) -> f32 {
+    let sinSquaredTransmitted: f32 = eta * eta * (1.0 - cosIncident * cosIncident);
+    if (sinSquaredTransmitted >= 1.0) {
+        return 1.0;
+    }
+
+    let cosTransmitted: f32 = sqrt(1.0 - sinSquaredTransmitted);
+    let rs: f32 = (eta * cosIncident - cosTransmitted) / (eta * cosIncident + cosTransmitted);
+    let rp: f32 = (cosIncident - eta * cosTransmitted) / (cosIncident + eta * cosTransmitted);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// Hue and saturation of a colour with its luminance normalised to one
+fn tint(color: vec3<f32>) -> vec3<f32> {
+    let luminance: f32 = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if (luminance <= 0.0) {
+        return vec3(1.0, 1.0, 1.0);
+    }
+    return color / luminance;
+}
+
+// Uniform point on the unit disk, or on a regular polygon inscribed in it
+// when there are at least three blades
+fn sampleAperture(blades: u32, u: vec3<f32>) -> vec2<f32> {
+    if (blades < 3u) {
+        let r: f32 = sqrt(u.x);
+        let phi: f32 = 2.0 * PI * u.y;
+        return vec2(r * cos(phi), r * sin(phi));
+    }
+
+    // Pick one of the triangles fanning out from the centre, then a point in it
+    let wedge: f32 = 2.0 * PI / f32(blades);
+    let corner: f32 = floor(u.z * f32(blades)) * wedge;
+    let a: vec2<f32> = vec2(cos(corner), sin(corner));
+    let b: vec2<f32> = vec2(cos(corner + wedge), sin(corner + wedge));
+    let s: f32 = sqrt(u.x);
+    return s * ((1.0 - u.y) * a + u.y * b);
+}
+
+fn sampleCosineHemisphere(u: vec2<f32>) -> vec3<f32> {
+    let r: f32 = sqrt(u.x);
+    let phi: f32 = 2.0 * PI * u.y;
+    return vec3(r * cos(phi), r * sin(phi), sqrt(max(0.0, 1.0 - u.x)));
+}
+
+fn orthonormalTangent(normal: vec3<f32>) -> vec3<f32> {
+    if (abs(normal.x) > 0.9) {
+        return normalize(cross(vec3(0.0, 1.0, 0.0), normal));
+    }
+    return normalize(cross(vec3(1.0, 0.0, 0.0), normal));
+}
+
+fn toWorld(v: vec3<f32>, tangent: vec3<f32>, bitangent: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    return normalize(v.x * tangent + v.y * bitangent + v.z * normal);
+}
+
+// Random numbers
+
+fn pcgHash(input: u32) -> u32 {
+    let state: u32 = input * 747796405u + 2891336453u;
+    let word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// The sampler draws from `count` dimensions starting at `first` until the
+// next call, so every decision of a path gets the same dimensions however
+// many numbers the ones before it took
+fn startDimensions(first: u32, count: u32) {
+    dimension = first;
+    dimensionEnd = first + count;
+}
+
+fn random() -> f32 {
+    if (frame.sampling == SAMPLER_PCG || dimension >= dimensionEnd) {
+        rngState = pcgHash(rngState);
+        return f32(rngState >> 8u) / 16777216.0;
+    }
+
+    let current: u32 = dimension;
+    dimension++;
+    if (frame.sampling == SAMPLER_SOBOL) {
+        return toUnit(sobol(current, pixelSeed));
+    }
+
+    // The same sequence everywhere, shifted by a blue noise tile moved
+    // around for every dimension
+    let size: vec2<u32> = textureDimensions(blueNoise);
+    let tile: vec2<u32> = (pixelCoordinates + vec2(pcgHash(current), pcgHash(current + 0x9e3779b9u))) % size;
+    let shift: f32 = textureLoad(blueNoise, vec2<i32>(tile), 0).r;
+    return fract(toUnit(sobol(current, frame.seed)) + shift);
+}
+
+// Two numbers from a pair of dimensions the sampler stratifies together
+fn random2() -> vec2<f32> {
+    if (frame.sampling != SAMPLER_PCG && dimension < dimensionEnd) {
+        dimension += dimension & 1u;
+    }
+    let x: f32 = random();
+    let y: f32 = random();
+    return vec2(x, y);
+}
+
+// Sample `frame.index` of Sobol's sequence along dimension `axis`. Only
+// the first two dimensions of the sequence are used, later pairs of
+// dimensions repeat them with the order of the samples shuffled, which
+// Burley shows is as good as the higher dimensions. Both the order and the
+// values are Owen scrambled with a hash of `seed`.
+fn sobol(axis: u32, seed: u32) -> u32 {
+    let pair: u32 = axis >> 1u;
+    let index: u32 = nestedUniformScramble(frame.index, pcgHash(seed ^ pcgHash(pair)));
+
+    var value: u32 = reverseBits(index);
+    if ((axis & 1u) != 0u) {
+        // Each bit of the index flips a row of Pascal's triangle modulo two
+        value = 0u;
+        var direction: u32 = 0x80000000u;
+        for (var bits: u32 = index; bits != 0u; bits >>= 1u) {
+            if ((bits & 1u) != 0u) {
+                value ^= direction;
+            }
+            direction ^= direction >> 1u;
+        }
+    }
+
+    return nestedUniformScramble(value, pcgHash(seed ^ pcgHash(axis + 0x68bc21ebu)));
+}
+
+// Owen scrambling, every bit flipped depending on the bits above it
+fn nestedUniformScramble(x: u32, seed: u32) -> u32 {
+    return reverseBits(laineKarras(reverseBits(x), seed));
+}
+
+// Hash where every bit only depends on the bits below it
+fn laineKarras(input: u32, seed: u32) -> u32 {
+    var x: u32 = input;
+    x ^= x * 0x3d20adeau;
+    x += seed;
+    x *= (seed >> 16u) | 1u;
+    x ^= x * 0x05526c56u;
+    x ^= x * 0x53a22864u;
+    return x;
+}
+
+fn toUnit(x: u32) -> f32 {
+    return f32(x >> 8u) / 16777216.0;
 }
@@ -0,0 +1,160 @@
+// Carries the image accumulated for the previous view over to the current
+// one. Runs after the first sample of a new view was traced: every pixel's
+// first hit is projected into the previous camera, the history found there
+// is checked against the depth it was accumulated at, and what survives is
+// added to the accumulation buffer as if it were earlier samples. Once the
+// view has more samples of its own, `expire` takes the history out again.
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(0) @binding(1) var<uniform> previousCamera: Camera;
+@group(0) @binding(2) var aovs: texture_2d_array<f32>;
+@group(0) @binding(3) var previousDepth: texture_2d<f32>;
+@group(0) @binding(4) var<storage, read> history: array<vec4<f32>>;
+@group(0) @binding(5) var<storage, read_write> accumulation: array<vec4<f32>>;
+@group(0) @binding(6) var colorBuffer: texture_storage_2d<rgba16float, write>;
+// What `main` added to every pixel, cleared before it runs
+@group(0) @binding(7) var<storage, read_write> added: array<vec4<f32>>;
+
+const AOV_DEPTH: i32 = 0;
+const AOV_POSITION: i32 = 4;
+
+const NO_HIT: f32 = 3.4e38;
+
+const PROJECTION_PERSPECTIVE: u32 = 0u;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1u;
+
+const STEREO_NONE: u32 = 0u;
+
+// Samples the history counts as at most, so it fades out over a few frames
+// of movement instead of smearing. Keep in sync with `MAX_HISTORY` in
+// reprojection.rs.
+const MAX_HISTORY: f32 = 8.0;
+
+// Relative depth difference at which the history is taken to show another
+// surface, one that was in front of or behind this pixel's
+const DEPTH_TOLERANCE: f32 = 0.05;
+
+struct Camera {
+    position: vec3<f32>,
+    aperture: f32,
+	forwards: vec3<f32>,
+    focusDistance: f32,
+	right: vec3<f32>,
+    blades: u32,
+	up: vec3<f32>,
+    projection: u32,
+    halfExtent: vec2<f32>,
+    eyeOffset: f32,
+    eyeConvergence: f32,
+    stereo: u32,
+    shutterOpen: f32,
+    shutterClose: f32,
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(colorBuffer);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    // Only flat images without stereo map points back to pixels
+    if (!reprojects(camera) || !reprojects(previousCamera)) {
+        return;
+    }
+    let pixel: vec2<i32> = vec2<i32>(id.xy);
+    let index: u32 = id.y * size.x + id.x;
+
+    // Misses are projected as directions, points infinitely far away
+    let depth: f32 = textureLoad(aovs, pixel, AOV_DEPTH, 0).x;
+    let miss: bool = depth >= NO_HIT;
+    var point: vec4<f32> = vec4(textureLoad(aovs, pixel, AOV_POSITION, 0).xyz, 1.0);
+    if (miss) {
+        if (camera.projection == PROJECTION_ORTHOGRAPHIC) {
+            point = vec4(camera.forwards, 0.0);
+        } else {
+            let uv: vec2<f32> = vec2(2.0, -2.0) * (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size) + vec2(-1.0, 1.0);
+            point = vec4(camera.forwards + uv.x * camera.halfExtent.x * camera.right + uv.y * camera.halfExtent.y * camera.up, 0.0);
+        }
+    }
+
+    // Distance along the previous view direction, as in the depth AOV
+    let offset: vec3<f32> = point.xyz - point.w * previousCamera.position;
+    let expectedDepth: f32 = dot(offset, previousCamera.forwards);
+    var uv: vec2<f32> = vec2(dot(offset, previousCamera.right), dot(offset, previousCamera.up));
+    if (previousCamera.projection == PROJECTION_PERSPECTIVE) {
+        if (expectedDepth <= 0.0) {
+            return;
+        }
+        uv /= expectedDepth;
+    } else if (miss) {
+        // Orthographic views see the same background everywhere
+        uv = vec2(0.0);
+    }
+    uv /= previousCamera.halfExtent;
+
+    // Texel centres sit at whole coordinates, so the four around it can be
+    // blended bilinearly
+    let coordinates: vec2<f32> = (vec2(0.5, -0.5) * uv + 0.5) * vec2<f32>(size) - 0.5;
+    let base: vec2<i32> = vec2<i32>(floor(coordinates));
+    let fraction: vec2<f32> = coordinates - floor(coordinates);
+
+    var color: vec3<f32> = vec3(0.0);
+    var samples: f32 = 0.0;
+    var weightSum: f32 = 0.0;
+    for (var y: i32 = 0; y <= 1; y++) {
+        for (var x: i32 = 0; x <= 1; x++) {
+            let position: vec2<i32> = base + vec2(x, y);
+            if (any(position < vec2(0)) || any(position >= vec2<i32>(size))) {
+                continue;
+            }
+
+            // Disocclusions, where the previous view saw something else
+            let historyDepth: f32 = textureLoad(previousDepth, position, 0).x;
+            var matches: bool = historyDepth >= NO_HIT && miss;
+            if (!miss && historyDepth < NO_HIT) {
+                matches = abs(historyDepth - expectedDepth) <= DEPTH_TOLERANCE * expectedDepth;
+            }
+            let texel: vec4<f32> = history[u32(position.y) * size.x + u32(position.x)];
+            if (!matches || texel.a <= 0.0) {
+                continue;
+            }
+
+            let weight: f32 = select(1.0 - fraction.x, fraction.x, x == 1) * select(1.0 - fraction.y, fraction.y, y == 1);
+            color += weight * texel.rgb / texel.a;
+            samples += weight * texel.a;
+            weightSum += weight;
+        }
+    }
+    if (weightSum < 1e-3) {
+        return;
+    }
+
+    let count: f32 = min(samples / weightSum, MAX_HISTORY);
+    let reprojected: vec4<f32> = vec4(count * color / weightSum, count);
+    let accumulated: vec4<f32> = accumulation[index] + reprojected;
+    accumulation[index] = accumulated;
+    added[index] = reprojected;
+    textureStore(colorBuffer, pixel, vec4(accumulated.rgb / accumulated.a, 1.0));
+}
+
+// Removes the history again, leaving only the samples of the current view
+@compute @workgroup_size(8, 8, 1)
+fn expire(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(colorBuffer);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let index: u32 = id.y * size.x + id.x;
+    if (added[index].a <= 0.0) {
+        return;
+    }
+
+    let accumulated: vec4<f32> = accumulation[index] - added[index];
+    accumulation[index] = accumulated;
+    added[index] = vec4(0.0);
+    textureStore(colorBuffer, vec2<i32>(id.xy), vec4(accumulated.rgb / accumulated.a, 1.0));
+}
+
+fn reprojects(camera: Camera) -> bool {
+    return camera.projection <= PROJECTION_ORTHOGRAPHIC && camera.stereo == STEREO_NONE;
+}
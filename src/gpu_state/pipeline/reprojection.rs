@@ -0,0 +1,331 @@
+use wgpu::{
+    BindGroup, Buffer, BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, Extent3d, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, Texture, TextureView,
+};
+use super::aov::{AOV_DEPTH, AOV_FORMAT};
+use super::camera::CameraUniform;
+use super::{ACCUMULATION_TEXEL_SIZE, RADIANCE_FORMAT};
+
+// Pixels per workgroup along each axis
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Samples the reprojected history counts as at most. Once a view has more
+/// of its own the history is expired.
+pub const MAX_HISTORY: u32 = 8;
+
+/// Temporal reprojection, keeps the image from turning to noise whenever
+/// the camera moves. Before the first sample of a new view overwrites them,
+/// the accumulated image and its depth are kept as history; after it, every
+/// pixel looks up where its first hit was seen by the previous camera and
+/// takes over the history there, unless the depths show something else was
+/// in the way. The history is only a stand-in for the first few samples, it
+/// is taken out again once the view has more than `MAX_HISTORY` samples, so
+/// converged images only show what the current view sees.
+pub struct Reprojection {
+    size: Extent3d,
+    pipeline: ComputePipeline,
+    expire_pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    previous_camera_buffer: Buffer,
+    history_buffer: Buffer,
+    history_depth: Texture,
+    added_buffer: Buffer,
+}
+
+impl Reprojection {
+    pub fn new(
+        device: &Device,
+        size: Extent3d,
+        camera_buffer: &Buffer,
+        aovs: &TextureView,
+        accumulation_buffer: &Buffer,
+        radiance: &TextureView,
+    ) -> Reprojection {
+        let previous_camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Previous Camera Buffer"),
+            size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let history_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("History Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * ACCUMULATION_TEXEL_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let added_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reprojected History Buffer"),
+            size: history_buffer.size(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let history_depth = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("History Depth Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: AOV_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let history_depth_view = history_depth.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Reproject Shader"),
+            source: ShaderSource::Wgsl(include_str!("reproject.wgsl").into()),
+        });
+
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform(0),
+                uniform(1),
+                texture(2, wgpu::TextureViewDimension::D2Array),
+                texture(3, wgpu::TextureViewDimension::D2),
+                storage(4, true),
+                storage(5, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: RADIANCE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                storage(7, false),
+            ],
+            label: Some("reproject_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: previous_camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(aovs),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&history_depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: history_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(radiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: added_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("reproject_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Reproject Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Reproject Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let expire_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Expire History Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "expire",
+        });
+
+        Reprojection {
+            size,
+            pipeline,
+            expire_pipeline,
+            bind_group,
+            previous_camera_buffer,
+            history_buffer,
+            history_depth,
+            added_buffer,
+        }
+    }
+
+    /// Uploads the camera the history was accumulated with.
+    pub fn set_previous_camera(&self, queue: &Queue, camera: CameraUniform) {
+        queue.write_buffer(&self.previous_camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+    }
+
+    /// Keeps the accumulated image and its depth, must come before the
+    /// first sample of the new view is traced.
+    pub fn save_history(&self, encoder: &mut CommandEncoder, accumulation_buffer: &Buffer, aov_texture: &Texture) {
+        encoder.copy_buffer_to_buffer(accumulation_buffer, 0, &self.history_buffer, 0, self.history_buffer.size());
+        encoder.copy_texture_to_texture(
+            wgpu::ImageCopyTexture {
+                texture: aov_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: AOV_DEPTH },
+                aspect: wgpu::TextureAspect::All,
+            },
+            self.history_depth.as_image_copy(),
+            self.size,
+        );
+    }
+
+    /// Adds the history to the first sample of the new view, must come
+    /// after it was traced.
+    pub fn run(&self, encoder: &mut CommandEncoder) {
+        encoder.clear_buffer(&self.added_buffer, 0, None);
+        self.dispatch(encoder, &self.pipeline, "Reproject Pass");
+    }
+
+    /// Takes the history `run` added out of the accumulated image again,
+    /// must come after a sample was traced.
+    pub fn expire(&self, encoder: &mut CommandEncoder) {
+        self.dispatch(encoder, &self.expire_pipeline, "Expire History Pass");
+    }
+
+    fn dispatch(&self, encoder: &mut CommandEncoder, pipeline: &ComputePipeline, label: &str) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some(label),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            self.size.width.div_ceil(WORKGROUP_SIZE),
+            self.size.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_state::testing::{ball_on_ground, rms_error, with_state};
+    use crate::{RenderOptions, Scene};
+
+    /// Renders `before` samples, moves the camera sideways and renders
+    /// `after` more.
+    fn render(scene: &Scene, temporal: bool, before: u32, after: u32) -> Option<Vec<[f32; 3]>> {
+        let options = RenderOptions {
+            temporal,
+            ..RenderOptions::default()
+        };
+        with_state(48, scene, &options, |state| {
+            state.render(before);
+            state.camera().translate([0.0, 0.1, 0.0]);
+            state.render(after);
+            state.read_radiance()
+        })
+    }
+
+    #[test]
+    fn moving_the_camera_keeps_the_reprojected_history() {
+        let scene = ball_on_ground();
+
+        let Some(reference) = render(&scene, false, 0, 256) else { return };
+        let single = render(&scene, false, 64, 1).unwrap();
+        let temporal = render(&scene, true, 64, 1).unwrap();
+
+        let single_error = rms_error(&single, &reference);
+        let temporal_error = rms_error(&temporal, &reference);
+        assert!(temporal_error < 0.5 * single_error, "{temporal_error} against {single_error}");
+    }
+
+    #[test]
+    fn the_history_expires_once_the_view_has_more_samples() {
+        let scene = ball_on_ground();
+
+        // Both trace the same samples after the move
+        let Some(plain) = render(&scene, false, 64, super::MAX_HISTORY + 1) else { return };
+        let expired = render(&scene, true, 64, super::MAX_HISTORY + 1).unwrap();
+        let kept = render(&scene, true, 64, super::MAX_HISTORY).unwrap();
+        let plain_before = render(&scene, false, 64, super::MAX_HISTORY).unwrap();
+
+        assert!(rms_error(&kept, &plain_before) > 1e-3, "the history was not reprojected");
+        let error = rms_error(&expired, &plain);
+        assert!(error < 1e-4, "{error}");
+    }
+
+    #[test]
+    fn animated_scenes_drop_the_history_when_the_time_changes() {
+        use crate::{ObjectAnimation, Transform};
+
+        let mut scene = ball_on_ground();
+        let animation = scene.add_animation(
+            ObjectAnimation::new()
+                .with_keyframe(0.0, Transform::IDENTITY)
+                .with_keyframe(1.0, Transform::IDENTITY.with_translation([0.0, 1.0, 0.0])),
+        );
+        scene.objects[1] = scene.objects[1].with_animation(animation);
+
+        let render_at_new_time = |temporal| {
+            let options = RenderOptions {
+                temporal,
+                ..RenderOptions::default()
+            };
+            with_state(48, &scene, &options, |state| {
+                state.render(64);
+                state.set_time(1.0);
+                state.render(1);
+                state.read_radiance()
+            })
+        };
+        let Some(plain) = render_at_new_time(false) else { return };
+        let temporal = render_at_new_time(true).unwrap();
+        assert_eq!(rms_error(&temporal, &plain), 0.0);
+    }
+}
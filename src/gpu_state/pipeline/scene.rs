@@ -0,0 +1,846 @@
+use std::path::{Path, PathBuf};
+use anyhow::{bail, ensure, Context, Result};
+use bytemuck::Zeroable;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use super::camera::{Camera, Fov, LookMode, Projection, Stereo};
+use super::material::{Material, MaterialStorage};
+use super::medium::{Medium, MediumStorage};
+use super::grid::{DensityGrid, GridAtlas};
+use super::animation::{ObjectAnimation, TransformKeyframe, TransformKeyStorage};
+use super::object::{ObjectStorage, SHAPE_SPHERE, SHAPE_CUBOID, HAS_SURFACE, HAS_MEDIUM};
+use super::world::WorldUniform;
+use super::post::PostEffects;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Sphere { center: [f32; 3], radius: f32 },
+    /// Axis aligned box spanning `min` to `max`.
+    Cuboid { min: [f32; 3], max: [f32; 3] },
+}
+
+impl Shape {
+    /// Distance along the ray to the first intersection in front of the
+    /// origin, in multiples of the length of `direction`.
+    pub fn intersect(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<f32> {
+        let dot = |a: [f32; 3], b: [f32; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        let (near, far) = match *self {
+            Shape::Sphere { center, radius } => {
+                let co = [0, 1, 2].map(|i| origin[i] - center[i]);
+                let a = dot(direction, direction);
+                let half_b = dot(direction, co) / a;
+                let closest = [0, 1, 2].map(|i| co[i] - half_b * direction[i]);
+                let discriminant = radius * radius - dot(closest, closest);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let half_width = (discriminant / a).sqrt();
+                (-half_b - half_width, -half_b + half_width)
+            }
+            Shape::Cuboid { min, max } => {
+                let mut near = f32::NEG_INFINITY;
+                let mut far = f32::INFINITY;
+                for i in 0..3 {
+                    let t0 = (min[i] - origin[i]) / direction[i];
+                    let t1 = (max[i] - origin[i]) / direction[i];
+                    near = near.max(t0.min(t1));
+                    far = far.min(t0.max(t1));
+                }
+                if near > far {
+                    return None;
+                }
+                (near, far)
+            }
+        };
+
+        [near, far].into_iter().find(|&t| t > 0.0)
+    }
+
+    /// Centre that animated objects are rotated and scaled about.
+    pub fn center(&self) -> [f32; 3] {
+        match *self {
+            Shape::Sphere { center, .. } => center,
+            Shape::Cuboid { min, max } => [0, 1, 2].map(|i| 0.5 * (min[i] + max[i])),
+        }
+    }
+}
+
+/// A shape with a surface material, a medium filling its interior, or both.
+/// Objects without a material have an invisible boundary that only marks
+/// where the medium starts.
+#[derive(Copy, Clone, Debug)]
+pub struct Object {
+    pub shape: Shape,
+    pub material: Option<Material>,
+    pub medium: Option<Medium>,
+    /// Index of the scene animation moving the object, if any.
+    pub animation: Option<usize>,
+}
+
+impl Object {
+    pub fn new(shape: Shape, material: Material) -> Object {
+        Object {
+            shape,
+            material: Some(material),
+            medium: None,
+            animation: None,
+        }
+    }
+
+    pub fn sphere(center: [f32; 3], radius: f32, material: Material) -> Object {
+        Object::new(Shape::Sphere { center, radius }, material)
+    }
+
+    pub fn cuboid(min: [f32; 3], max: [f32; 3], material: Material) -> Object {
+        Object::new(Shape::Cuboid { min, max }, material)
+    }
+
+    pub fn volume(shape: Shape, medium: Medium) -> Object {
+        Object {
+            shape,
+            material: None,
+            medium: Some(medium),
+            animation: None,
+        }
+    }
+
+    pub fn with_animation(self, animation: usize) -> Object {
+        Object {
+            animation: Some(animation),
+            ..self
+        }
+    }
+
+    pub fn new_random() -> Object {
+        let center = [10.0 + 10.0*random::<f32>(), 10.0*random::<f32>(), 10.0*random::<f32>()];
+        let color = [random::<f32>(), random::<f32>(), random::<f32>()];
+        let radius = 5.0 * random::<f32>();
+        let material = Material {
+            base_color: color,
+            metallic: random::<f32>().round(),
+            roughness: random::<f32>(),
+            ..Default::default()
+        };
+        Object::sphere(center, radius, material)
+    }
+
+    pub fn into_storage(self) -> ObjectStorage {
+        let center = self.shape.center();
+        let (shape, size) = match self.shape {
+            Shape::Sphere { radius, .. } => (SHAPE_SPHERE, [radius, radius, radius]),
+            Shape::Cuboid { min, max } => (SHAPE_CUBOID, [0, 1, 2].map(|i| 0.5 * (max[i] - min[i]).abs())),
+        };
+
+        let mut flags = 0;
+        if self.material.is_some() {
+            flags |= HAS_SURFACE;
+        }
+        if self.medium.is_some() {
+            flags |= HAS_MEDIUM;
+        }
+
+        ObjectStorage::new(
+            shape,
+            center,
+            size,
+            flags,
+            self.material.map_or_else(MaterialStorage::zeroed, Material::into_storage),
+            self.medium.map_or_else(MediumStorage::zeroed, Medium::into_storage),
+        )
+    }
+}
+
+/// Everything the tracer needs to know about the world before the first
+/// frame is drawn.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub camera: Camera,
+    pub objects: Vec<Object>,
+    /// Radiance of rays that escape the scene.
+    pub background: [f32; 3],
+    /// Medium filling all space outside of other media.
+    pub fog: Option<Medium>,
+    /// Density grids referenced by heterogeneous media.
+    pub grids: Vec<DensityGrid>,
+    /// Keyframed transforms referenced by animated objects.
+    pub animations: Vec<ObjectAnimation>,
+    /// Lens and film effects, none are on by default.
+    pub post: PostEffects,
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Scene {
+        Scene {
+            camera,
+            objects: Vec::new(),
+            background: [1.0, 1.0, 1.0],
+            fog: None,
+            grids: Vec::new(),
+            animations: Vec::new(),
+            post: PostEffects::default(),
+        }
+    }
+
+    /// Adds an object, failing if it refers to a density grid or animation
+    /// the scene does not have yet, or if its animation scales it to nothing.
+    pub fn add(&mut self, object: Object) -> Result<&mut Scene> {
+        self.check(&object)?;
+        self.objects.push(object);
+        Ok(self)
+    }
+
+    fn check(&self, object: &Object) -> Result<()> {
+        if let Some(grid) = object.medium.and_then(|m| m.density_grid) {
+            ensure!(grid < self.grids.len(), "medium refers to missing density grid {}", grid);
+        }
+        if let Some(index) = object.animation {
+            let Some(animation) = self.animations.get(index) else {
+                bail!("object refers to missing animation {}", index);
+            };
+            // Scales are interpolated linearly, so a sign change between
+            // keyframes passes through zero as well
+            let scales: Vec<[f32; 3]> = animation.keyframes().iter().map(|k| k.transform.scale).collect();
+            for axis in 0..3 {
+                ensure!(
+                    scales.iter().all(|s| s[axis] > 0.0) || scales.iter().all(|s| s[axis] < 0.0),
+                    "animation {} scales the object to nothing along axis {}",
+                    index,
+                    axis
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks what `add` checks for every object, including those pushed
+    /// onto `objects` directly.
+    pub fn validate(&self) -> Result<()> {
+        for (i, object) in self.objects.iter().enumerate() {
+            self.check(object).with_context(|| format!("object {}", i))?;
+        }
+        Ok(())
+    }
+
+    /// Reference scene: a Cornell box, open towards the camera, lit by a
+    /// ceiling lamp, with a block of dark soot and a block of white smoke.
+    pub fn smoky_cornell_box() -> Scene {
+        let white = Material::diffuse([0.73, 0.73, 0.73]);
+        let red = Material::diffuse([0.65, 0.05, 0.05]);
+        let green = Material::diffuse([0.12, 0.45, 0.15]);
+
+        let mut scene = Scene::new(Camera::new([-2.4, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        scene.background = [0.0, 0.0, 0.0];
+        scene.objects.extend([
+            Object::cuboid([0.0, -1.0, -1.1], [2.0, 1.0, -1.0], white),
+            Object::cuboid([0.0, -1.0, 1.0], [2.0, 1.0, 1.1], white),
+            Object::cuboid([2.0, -1.0, -1.0], [2.1, 1.0, 1.0], white),
+            Object::cuboid([0.0, 1.0, -1.0], [2.0, 1.1, 1.0], red),
+            Object::cuboid([0.0, -1.1, -1.0], [2.0, -1.0, 1.0], green),
+            Object::cuboid([0.7, -0.3, 0.98], [1.3, 0.3, 0.995], Material::emissive([15.0, 15.0, 15.0])),
+            Object::volume(Shape::Cuboid { min: [0.9, 0.1, -0.999], max: [1.5, 0.7, 0.2] }, Medium::soot(4.0)),
+            Object::volume(Shape::Cuboid { min: [0.4, -0.7, -0.999], max: [1.0, -0.1, -0.4] }, Medium::smoke(6.0)),
+        ]);
+        scene
+    }
+
+    /// Distance to the nearest visible surface along a ray with the objects
+    /// where they are at `time`, volumes are ignored.
+    pub fn raycast(&self, origin: [f32; 3], direction: [f32; 3], time: f32) -> Option<f32> {
+        self.objects
+            .iter()
+            .filter(|o| o.material.is_some())
+            .filter_map(|o| match o.animation {
+                Some(animation) => {
+                    let transform = self.animations[animation].sample(time);
+                    let center = o.shape.center();
+                    o.shape.intersect(transform.point_to_rest(center, origin), transform.direction_to_rest(direction))
+                }
+                None => o.shape.intersect(origin, direction),
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    /// Adds a density grid and returns the index media refer to it by.
+    pub fn add_grid(&mut self, grid: DensityGrid) -> usize {
+        self.grids.push(grid);
+        self.grids.len() - 1
+    }
+
+    /// Adds an animation and returns the index objects refer to it by.
+    pub fn add_animation(&mut self, animation: ObjectAnimation) -> usize {
+        self.animations.push(animation);
+        self.animations.len() - 1
+    }
+
+    /// Time of the last keyframe of any object.
+    pub fn animation_duration(&self) -> f32 {
+        self.animations.iter().map(ObjectAnimation::duration).fold(0.0, f32::max)
+    }
+
+    pub fn object_storage(&self) -> Result<Vec<ObjectStorage>> {
+        self.validate()?;
+
+        // Keyframes of every animation are stored back to back
+        let mut offsets = Vec::with_capacity(self.animations.len());
+        let mut offset = 0;
+        for animation in &self.animations {
+            offsets.push(offset);
+            offset += animation.keyframes().len() as u32;
+        }
+
+        // Objects sharing a material share its id
+        let mut materials: Vec<Material> = Vec::new();
+        let mut material_id = |material: Material| match materials.iter().position(|&m| m == material) {
+            Some(id) => id as u32,
+            None => {
+                materials.push(material);
+                materials.len() as u32 - 1
+            }
+        };
+
+        let mut storage: Vec<ObjectStorage> = self
+            .objects
+            .iter()
+            .map(|o| {
+                let storage = match o.animation {
+                    Some(animation) => {
+                        let count = self.animations[animation].keyframes().len() as u32;
+                        o.into_storage().with_keyframes(offsets[animation], count)
+                    }
+                    None => o.into_storage(),
+                };
+                match o.material {
+                    Some(material) => storage.with_material_id(material_id(material)),
+                    None => storage,
+                }
+            })
+            .collect();
+        // Storage bindings can not be empty, a zeroed sphere has no radius
+        // and is never hit.
+        if storage.is_empty() {
+            storage.push(ObjectStorage::zeroed());
+        }
+        Ok(storage)
+    }
+
+    pub fn keyframe_storage(&self) -> Vec<TransformKeyStorage> {
+        let mut storage: Vec<TransformKeyStorage> = self
+            .animations
+            .iter()
+            .flat_map(|a| a.keyframes().iter().map(|k| k.into_storage()))
+            .collect();
+        // Like objects, the binding needs at least one element
+        if storage.is_empty() {
+            storage.push(TransformKeyStorage::zeroed());
+        }
+        storage
+    }
+
+    /// The density grids stacked into one texture, which fails if it gets
+    /// larger than `max_size` in any dimension.
+    pub fn grid_atlas(&self, max_size: u32) -> Result<GridAtlas> {
+        GridAtlas::new(&self.grids, max_size)
+    }
+
+    pub fn world_uniform(&self) -> WorldUniform {
+        WorldUniform::new(self.background, self.fog.map(Medium::into_storage))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraFile,
+    // Checked for unknown keys before being read as `ObjectFile`s
+    #[serde(default)]
+    objects: Vec<toml::Table>,
+    #[serde(default = "white")]
+    background: [f32; 3],
+    fog: Option<Medium>,
+    #[serde(default)]
+    grids: Vec<GridFile>,
+    #[serde(default)]
+    post: PostEffects,
+}
+
+/// A density grid file, relative to the scene file. Raw files of
+/// little endian `f32`s need their `dimensions`, other formats have them in
+/// their header.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GridFile {
+    path: PathBuf,
+    dimensions: Option<[u32; 3]>,
+}
+
+impl GridFile {
+    fn load(self, directory: &Path) -> Result<DensityGrid> {
+        let path = directory.join(self.path);
+        match self.dimensions {
+            Some(dimensions) => DensityGrid::load_raw(path, dimensions),
+            None => DensityGrid::load(path),
+        }
+    }
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraFile {
+    position: [f32; 3],
+    forwards: [f32; 3],
+    /// Point to look at, takes precedence over `forwards`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    look_at: Option<[f32; 3]>,
+    /// Degrees rolled clockwise around the view direction.
+    roll: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fov: Option<Fov>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    projection: Option<Projection>,
+    aperture: f32,
+    focus_distance: f32,
+    blades: u32,
+    shutter: [f32; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stereo: Option<Stereo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    look_mode: Option<LookMode>,
+}
+
+impl Default for CameraFile {
+    fn default() -> CameraFile {
+        CameraFile {
+            position: [0.0; 3],
+            forwards: [1.0, 0.0, 0.0],
+            look_at: None,
+            roll: 0.0,
+            fov: None,
+            projection: None,
+            aperture: 0.0,
+            focus_distance: 10.0,
+            blades: 0,
+            shutter: [0.0; 2],
+            stereo: None,
+            look_mode: None,
+        }
+    }
+}
+
+impl CameraFile {
+    fn from_camera(camera: &Camera) -> CameraFile {
+        CameraFile {
+            position: camera.position(),
+            forwards: camera.direction(),
+            look_at: None,
+            roll: camera.roll(),
+            fov: Some(camera.fov()),
+            projection: Some(camera.projection()),
+            aperture: camera.aperture(),
+            focus_distance: camera.focus_distance(),
+            blades: camera.blades(),
+            shutter: camera.shutter(),
+            stereo: camera.stereo(),
+            look_mode: Some(camera.look_mode()),
+        }
+    }
+
+    fn into_camera(self) -> Result<Camera> {
+        let forwards = match self.look_at {
+            Some(target) => [0, 1, 2].map(|i| target[i] - self.position[i]),
+            None => self.forwards,
+        };
+        if let Some(fov) = self.fov {
+            fov.validate()?;
+        }
+        if let Some(projection) = self.projection {
+            projection.validate()?;
+        }
+        if let Some(stereo) = self.stereo {
+            stereo.validate()?;
+        }
+        if forwards == [0.0; 3] {
+            match self.look_at {
+                Some(_) => bail!("camera looks at its own position"),
+                None => bail!("camera has no forwards direction"),
+            }
+        }
+        // The look mode comes first, in first person mode the view is kept
+        // from pointing straight up or down
+        let mut camera = Camera::new(self.position, forwards)
+            .with_look_mode(self.look_mode.unwrap_or(LookMode::FirstPerson))
+            .with_lens(self.aperture, self.focus_distance, self.blades)
+            .with_shutter(self.shutter[0], self.shutter[1]);
+        camera.set_view(self.position, forwards);
+        camera.roll_rightwards(self.roll / 360.0);
+        if let Some(fov) = self.fov {
+            camera.set_fov(fov);
+        }
+        if let Some(projection) = self.projection {
+            camera.set_projection(projection);
+        }
+        camera.set_stereo(self.stereo);
+        Ok(camera)
+    }
+}
+
+/// The `[camera]` table of a scene file recreating `camera`.
+pub fn camera_to_toml(camera: &Camera) -> String {
+    #[derive(Serialize)]
+    struct Table {
+        camera: CameraFile,
+    }
+    toml::to_string(&Table { camera: CameraFile::from_camera(camera) }).expect("cameras serialise to TOML")
+}
+
+// Shapes are flattened, so an object is written as `sphere = { ... }` or
+// `cuboid = { ... }` next to its material. Flattening keeps serde from
+// denying unknown fields, they are checked against `OBJECT_KEYS` instead.
+#[derive(Deserialize)]
+struct ObjectFile {
+    #[serde(flatten)]
+    shape: Shape,
+    material: Option<Material>,
+    medium: Option<Medium>,
+    #[serde(default)]
+    animation: Vec<TransformKeyframe>,
+}
+
+const OBJECT_KEYS: [&str; 5] = ["sphere", "cuboid", "material", "medium", "animation"];
+
+impl Scene {
+    /// Parses a scene description:
+    ///
+    /// ```toml
+    /// background = [0.0, 0.0, 0.0]
+    ///
+    /// [camera]
+    /// position = [-2.4, 0.0, 0.0]
+    /// look_at = [1.0, 0.0, 0.0]
+    /// fov = { horizontal = 53.13 }
+    /// shutter = [0.0, 0.02]
+    ///
+    /// [[objects]]
+    /// sphere = { center = [1.0, 0.0, 0.0], radius = 0.5 }
+    /// material = { base_color = [0.8, 0.1, 0.1], roughness = 0.3 }
+    /// animation = [
+    ///     { time = 0.0 },
+    ///     { time = 2.0, translation = [0.0, -1.0, 0.0] },
+    /// ]
+    ///
+    /// [[objects]]
+    /// cuboid = { min = [0.5, -1.0, -1.0], max = [1.5, 1.0, 1.0] }
+    /// medium = { scattering = [2.0, 2.0, 2.0] }
+    ///
+    /// [[grids]]
+    /// path = "cloud.vol"
+    ///
+    /// [[objects]]
+    /// sphere = { center = [1.0, 0.0, 1.0], radius = 0.5 }
+    /// medium = { scattering = [4.0, 4.0, 4.0], density_grid = 0 }
+    ///
+    /// [post.bloom]
+    /// intensity = 0.05
+    /// ```
+    ///
+    /// Besides the keys above, the camera takes `roll` in degrees, a
+    /// `projection` like `{ type = "orthographic", view_height = 2.0 }`,
+    /// `stereo = { interpupillary_distance = 0.064, layout = "side_by_side" }`
+    /// and `look_mode = "free"`.
+    ///
+    /// Objects need a material, a medium or both. Keyframes give the
+    /// object's translation, `[x, y, z, w]` rotation quaternion and scale at
+    /// `time` seconds, leaving any of them out means no change. Media refer
+    /// to the `[[grids]]` by their index, a grid takes a `path` that is
+    /// relative to the scene file and, for headerless files of `f32`s, the
+    /// grid's `dimensions`. The `[post]` table is described by `PostEffects`.
+    ///
+    /// Grid paths are relative to the working directory here, `load`
+    /// resolves them against the scene file.
+    pub fn from_toml(text: &str) -> Result<Scene> {
+        Scene::parse(text, Path::new(""))
+    }
+
+    fn parse(text: &str, directory: &Path) -> Result<Scene> {
+        let file: SceneFile = toml::from_str(text)?;
+
+        let mut scene = Scene::new(file.camera.into_camera().context("camera")?);
+        scene.background = file.background;
+        if file.fog.is_some_and(|fog| fog.density_grid.is_some()) {
+            bail!("fog can't have a density grid");
+        }
+        scene.fog = file.fog;
+        scene.post = file.post;
+        for (i, grid) in file.grids.into_iter().enumerate() {
+            scene.add_grid(grid.load(directory).with_context(|| format!("grid {}", i))?);
+        }
+        for (i, table) in file.objects.into_iter().enumerate() {
+            if let Some(key) = table.keys().find(|key| !OBJECT_KEYS.contains(&key.as_str())) {
+                bail!("object {} has unknown field `{}`, expected one of {:?}", i, key, OBJECT_KEYS);
+            }
+            let object: ObjectFile = toml::Value::Table(table).try_into().with_context(|| format!("object {}", i))?;
+            if object.material.is_none() && object.medium.is_none() {
+                bail!("object {} has neither a material nor a medium", i);
+            }
+            let mut animated = Object {
+                shape: object.shape,
+                material: object.material,
+                medium: object.medium,
+                animation: None,
+            };
+            if !object.animation.is_empty() {
+                let mut animation = ObjectAnimation::new();
+                for keyframe in object.animation {
+                    animation.insert(keyframe);
+                }
+                animated = animated.with_animation(scene.add_animation(animation));
+            }
+            scene.add(animated).with_context(|| format!("object {}", i))?;
+        }
+        Ok(scene)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Scene> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        Scene::parse(&text, directory).with_context(|| format!("loading {}", path.display()))
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        let mut scene = Scene::new(Camera::default());
+        scene.objects.push(Object::sphere([10.0, 0.0, 0.0], 1.0, Material::diffuse([1.0, 0.0, 0.0])));
+        for _ in 0..7 {
+            scene.objects.push(Object::new_random());
+        }
+        scene
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bloom;
+
+    #[test]
+    fn rays_hit_spheres_from_outside_and_inside() {
+        let sphere = Shape::Sphere { center: [5.0, 0.0, 0.0], radius: 1.0 };
+        assert_eq!(sphere.intersect([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]), Some(4.0));
+        assert_eq!(sphere.intersect([5.0, 0.0, 0.0], [1.0, 0.0, 0.0]), Some(1.0));
+        assert_eq!(sphere.intersect([0.0, 0.0, 0.0], [-1.0, 0.0, 0.0]), None);
+        assert_eq!(sphere.intersect([0.0, 2.0, 0.0], [1.0, 0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn rays_hit_cuboids_from_outside_and_inside() {
+        let cuboid = Shape::Cuboid { min: [2.0, -1.0, -1.0], max: [3.0, 1.0, 1.0] };
+        assert_eq!(cuboid.intersect([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]), Some(2.0));
+        assert_eq!(cuboid.intersect([2.5, 0.0, 0.0], [1.0, 0.0, 0.0]), Some(0.5));
+        assert_eq!(cuboid.intersect([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]), None);
+    }
+
+    #[test]
+    fn raycast_finds_nearest_surface_and_skips_volumes() {
+        let mut scene = Scene::new(Camera::default());
+        scene.add(Object::volume(Shape::Sphere { center: [2.0, 0.0, 0.0], radius: 1.0 }, Medium::smoke(1.0))).unwrap();
+        scene.add(Object::sphere([10.0, 0.0, 0.0], 1.0, Material::default())).unwrap();
+        scene.add(Object::sphere([6.0, 0.0, 0.0], 1.0, Material::default())).unwrap();
+        assert_eq!(scene.raycast([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 0.0), Some(5.0));
+        assert_eq!(scene.raycast([0.0, 0.0, 0.0], [0.0, 0.0, 1.0], 0.0), None);
+    }
+
+    #[test]
+    fn raycast_finds_animated_objects_where_they_are_at_the_time() {
+        use super::super::animation::Transform;
+
+        let mut scene = Scene::new(Camera::default());
+        let animation = scene.add_animation(
+            ObjectAnimation::new()
+                .with_keyframe(0.0, Transform::IDENTITY)
+                .with_keyframe(1.0, Transform::IDENTITY.with_translation([0.0, 4.0, 0.0]).with_scale([2.0, 1.0, 1.0])),
+        );
+        scene.add(Object::sphere([5.0, 0.0, 0.0], 1.0, Material::default()).with_animation(animation)).unwrap();
+        assert_eq!(scene.raycast([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 0.0), Some(4.0));
+        assert_eq!(scene.raycast([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 1.0), None);
+        assert_eq!(scene.raycast([0.0, 4.0, 0.0], [1.0, 0.0, 0.0], 1.0), Some(3.0));
+        assert_eq!(scene.animation_duration(), 1.0);
+    }
+
+    #[test]
+    fn scenes_load_from_toml() {
+        let scene = Scene::from_toml(
+            r#"
+            background = [0.0, 0.0, 0.0]
+
+            [camera]
+            position = [-2.0, 0.0, 0.0]
+            look_at = [0.0, 0.0, 0.0]
+            shutter = [0.0, 0.5]
+
+            [[objects]]
+            sphere = { center = [1.0, 0.0, 0.0], radius = 0.5 }
+            material = { base_color = [0.8, 0.1, 0.1] }
+            animation = [{ time = 0.0 }, { time = 2.0, translation = [0.0, 1.0, 0.0] }]
+
+            [[objects]]
+            cuboid = { min = [0, -1, -1], max = [1, 1, 1] }
+            medium = { scattering = [2.0, 2.0, 2.0] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(scene.background, [0.0, 0.0, 0.0]);
+        assert_eq!(scene.camera.shutter(), [0.0, 0.5]);
+        assert_eq!(scene.objects.len(), 2);
+        assert_eq!(scene.objects[0].material.unwrap().base_color, [0.8, 0.1, 0.1]);
+        assert_eq!(scene.objects[0].material.unwrap().roughness, Material::default().roughness);
+        assert_eq!(scene.objects[1].shape, Shape::Cuboid { min: [0.0, -1.0, -1.0], max: [1.0, 1.0, 1.0] });
+        assert_eq!(scene.objects[1].medium, Some(Medium::new([0.0; 3], [2.0; 3], 0.0)));
+        assert_eq!(scene.animation_duration(), 2.0);
+        assert_eq!(scene.raycast([-2.0, 0.0, 0.0], [1.0, 0.0, 0.0], 0.0), Some(2.5));
+    }
+
+    #[test]
+    fn cameras_are_written_back_as_toml() {
+        let camera = Camera::new([1.0, 2.0, 3.0], [0.0, 1.0, 0.0])
+            .with_fov(Fov::Vertical(40.0))
+            .with_lens(0.1, 4.0, 6)
+            .with_shutter(0.0, 0.25);
+        let scene = Scene::from_toml(&camera_to_toml(&camera)).unwrap();
+
+        assert_eq!(scene.camera.position(), [1.0, 2.0, 3.0]);
+        let direction = scene.camera.direction();
+        assert!((direction[1] - 1.0).abs() < 1e-5, "{direction:?}");
+        assert_eq!(scene.camera.fov(), Fov::Vertical(40.0));
+        assert_eq!((scene.camera.aperture(), scene.camera.focus_distance(), scene.camera.blades()), (0.1, 4.0, 6));
+        assert_eq!(scene.camera.shutter(), [0.0, 0.25]);
+        assert_eq!(scene.camera.projection(), Projection::Perspective);
+        assert_eq!(scene.camera.stereo(), None);
+        assert!(scene.camera.roll().abs() < 1e-3, "{}", scene.camera.roll());
+    }
+
+    #[test]
+    fn rolled_panoramic_stereo_cameras_are_written_back_as_toml() {
+        use super::super::camera::{FisheyeMapping, StereoLayout};
+
+        let mut camera = Camera::new([0.0; 3], [1.0, 0.0, 0.0])
+            .with_look_mode(LookMode::Free)
+            .with_projection(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 200.0 })
+            .with_stereo(Stereo::new(0.064, f32::INFINITY, StereoLayout::OverUnder).unwrap());
+        camera.rotate_upwards(0.25);
+        camera.roll_rightwards(0.1);
+        let text = camera_to_toml(&camera);
+        let loaded = Scene::from_toml(&text).unwrap().camera;
+
+        assert_eq!(loaded.look_mode(), LookMode::Free);
+        assert_eq!(loaded.projection(), camera.projection());
+        assert_eq!(loaded.stereo(), camera.stereo());
+        assert!((loaded.roll() - camera.roll()).abs() < 0.01, "{} against {}", loaded.roll(), camera.roll());
+        let (forwards, right, up) = loaded.axes();
+        let (expected_forwards, expected_right, expected_up) = camera.axes();
+        for (a, b) in [(forwards, expected_forwards), (right, expected_right), (up, expected_up)] {
+            assert!((0..3).all(|i| (a[i] - b[i]).abs() < 1e-4), "{text}\n{a:?} against {b:?}");
+        }
+    }
+
+    #[test]
+    fn post_effects_are_on_when_they_have_a_table() {
+        let scene = Scene::from_toml("[post.bloom]\nintensity = 0.1\n\n[post.grain]\nenabled = false").unwrap();
+        assert_eq!(scene.post.bloom, Bloom { intensity: 0.1, ..Bloom::default() });
+        assert!(scene.post.bloom.enabled);
+        assert!(!scene.post.grain.enabled && !scene.post.vignette.enabled && !scene.post.lens.enabled);
+        assert!(!Scene::from_toml("").unwrap().post.any());
+    }
+
+    #[test]
+    fn scene_errors_are_reported() {
+        assert!(Scene::from_toml("[[objects]]\nsphere = { center = [0, 0, 0], radius = 1 }").is_err());
+        assert!(Scene::from_toml("[[objects]]\ncone = { radius = 1 }\nmaterial = {}").is_err());
+        assert!(Scene::from_toml("[camera]\nzoom = 2").is_err());
+        assert!(Scene::from_toml("[[objects]]\nsphere = { center = [0, 0, 0], radius = 1 }\nmaterial = {}\nmaterail = {}").is_err());
+        assert!(Scene::from_toml("[camera]\nposition = [1, 2, 3]\nlook_at = [1, 2, 3]").is_err());
+        assert!(Scene::from_toml("[camera]\nforwards = [0, 0, 0]").is_err());
+        assert!(Scene::from_toml("[camera]\nstereo = { interpupillary_distance = 0.064, convergence = 0, layout = \"side_by_side\" }").is_err());
+        assert!(Scene::from_toml("[camera]\nstereo = { interpupillary_distance = 0.064, layout = \"side_by_side\" }").is_ok());
+        assert!(Scene::from_toml("[camera]\nfov = { vertical = 180 }").is_err());
+        assert!(Scene::from_toml("[camera]\nfov = { horizontal = 200 }").is_err());
+        assert!(Scene::from_toml("[camera]\nprojection = { type = \"orthographic\", view_height = 0 }").is_err());
+        assert!(Scene::from_toml("[camera]\nprojection = { type = \"orthographic\", view_height = 2 }").is_ok());
+    }
+
+    #[test]
+    fn objects_must_refer_to_existing_grids() {
+        let cloud = Object::volume(Shape::Sphere { center: [0.0; 3], radius: 1.0 }, Medium::smoke(1.0).with_density_grid(0));
+        let mut scene = Scene::new(Camera::default());
+        assert!(scene.add(cloud).is_err());
+        scene.objects.push(cloud);
+        assert!(scene.object_storage().is_err());
+
+        scene.add_grid(DensityGrid::from_fn([2, 2, 2], |_| 1.0).unwrap());
+        assert!(scene.add(cloud).is_ok());
+        assert!(scene.object_storage().is_ok());
+    }
+
+    #[test]
+    fn grids_load_relative_to_the_scene_file() {
+        let directory = std::env::temp_dir().join(format!("scene-grids-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let densities: Vec<u8> = [0.0f32, 0.5, 1.0, 2.0].iter().flat_map(|d| d.to_le_bytes()).collect();
+        std::fs::write(directory.join("cloud.raw"), densities).unwrap();
+        let file = directory.join("cloud.toml");
+        std::fs::write(
+            &file,
+            r#"
+            [[grids]]
+            path = "cloud.raw"
+            dimensions = [2, 2, 1]
+
+            [[objects]]
+            sphere = { center = [2.0, 0.0, 0.0], radius = 1.0 }
+            medium = { scattering = [4.0, 4.0, 4.0], density_grid = 0 }
+            "#,
+        )
+        .unwrap();
+        let scene = Scene::load(&file);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let scene = scene.unwrap();
+        assert_eq!(scene.grids, vec![DensityGrid::new([2, 2, 1], vec![0.0, 0.5, 1.0, 2.0]).unwrap()]);
+        assert_eq!(scene.objects[0].medium.unwrap().density_grid, Some(0));
+
+        // Relative to the working directory, where there is no cloud
+        assert!(Scene::from_toml("[[grids]]\npath = \"cloud.raw\"\ndimensions = [2, 2, 1]").is_err());
+        assert!(Scene::from_toml("[[objects]]\nsphere = { center = [0, 0, 0], radius = 1 }\nmedium = { density_grid = 0 }").is_err());
+        assert!(Scene::from_toml("fog = { scattering = [0.1, 0.1, 0.1], density_grid = 0 }").is_err());
+    }
+
+    #[test]
+    fn objects_must_refer_to_existing_animations_that_keep_their_size() {
+        use super::super::animation::Transform;
+
+        let ball = Object::sphere([0.0; 3], 1.0, Material::default());
+        let mut scene = Scene::new(Camera::default());
+        assert!(scene.add(ball.with_animation(0)).is_err());
+
+        let flat = scene.add_animation(ObjectAnimation::new().with_keyframe(0.0, Transform::IDENTITY.with_scale([1.0, 0.0, 1.0])));
+        assert!(scene.add(ball.with_animation(flat)).is_err());
+        let flipping = scene.add_animation(
+            ObjectAnimation::new()
+                .with_keyframe(0.0, Transform::IDENTITY)
+                .with_keyframe(1.0, Transform::IDENTITY.with_scale([-1.0, 1.0, 1.0])),
+        );
+        assert!(scene.add(ball.with_animation(flipping)).is_err());
+        let mirrored = scene.add_animation(ObjectAnimation::new().with_keyframe(0.0, Transform::IDENTITY.with_scale([-1.0, 2.0, 1.0])));
+        assert!(scene.add(ball.with_animation(mirrored)).is_ok());
+
+        assert!(Scene::from_toml("[[objects]]\nsphere = { center = [0, 0, 0], radius = 1 }\nmaterial = {}\nanimation = [{ time = 0.0, scale = [0, 1, 1] }]").is_err());
+    }
+}
@@ -20,10 +20,122 @@ fn vs_main(input: VertexInput) -> VertexOutput {
 
 // Fragment shader
 
+const TONE_MAPPER_CLAMP: u32 = 0u;
+const TONE_MAPPER_REINHARD: u32 = 1u;
+const TONE_MAPPER_ACES: u32 = 2u;
+const TONE_MAPPER_AGX: u32 = 3u;
+const TONE_MAPPER_UNCHARTED_2: u32 = 4u;
+
+const AOV_DEPTH: u32 = 0u;
+const AOV_NORMAL: u32 = 1u;
+const AOV_ALBEDO: u32 = 2u;
+const AOV_ID: u32 = 3u;
+const AOV_POSITION: u32 = 4u;
+
+struct Display {
+    exposureScale: f32,
+    toneMapper: u32,
+    encodeSrgb: u32,
+    aov: u32,
+}
+
 @group(0) @binding(0) var color_buffer: texture_2d<f32>;
 @group(0) @binding(1) var screen_sampler: sampler;
+@group(0) @binding(2) var<uniform> display: Display;
+@group(0) @binding(3) var aovs: texture_2d_array<f32>;
 
 @fragment
 fn fs_main(@location(0) tex_coords: vec2<f32>) -> @location(0) vec4<f32> {
-    return textureSample(color_buffer, screen_sampler, tex_coords);
+    let radiance: vec3<f32> = max(textureSample(color_buffer, screen_sampler, tex_coords).rgb, vec3(0.0));
+    var color: vec3<f32> = toneMap(radiance * display.exposureScale);
+    if (display.aov > 0u) {
+        color = showAov(display.aov - 1u, tex_coords);
+    }
+
+    // sRGB surfaces encode by themselves, others expect encoded values
+    if (display.encodeSrgb != 0u) {
+        color = encodeSrgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// False colours of an AOV, which can not be filtered
+fn showAov(layer: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let size: vec2<u32> = textureDimensions(aovs);
+    let texel: vec2<u32> = min(vec2<u32>(tex_coords * vec2<f32>(size)), size - 1u);
+    let value: vec4<f32> = textureLoad(aovs, texel, i32(layer), 0);
+
+    if (layer == AOV_DEPTH) {
+        // Near is bright, nothing is black
+        return vec3(1.0 / (1.0 + 0.1 * max(value.x, 0.0)));
+    } else if (layer == AOV_NORMAL) {
+        return 0.5 * value.xyz + 0.5;
+    } else if (layer == AOV_ID) {
+        if (value.x < 0.0) {
+            return vec3(0.0);
+        }
+        let hash: u32 = idHash(u32(value.x));
+        return vec3(f32(hash & 255u), f32((hash >> 8u) & 255u), f32((hash >> 16u) & 255u)) / 255.0;
+    } else if (layer == AOV_POSITION) {
+        // A unit grid in every direction
+        return fract(value.xyz);
+    }
+    return clamp(value.xyz, vec3(0.0), vec3(1.0));
+}
+
+fn idHash(id: u32) -> u32 {
+    let state: u32 = id * 747796405u + 2891336453u;
+    let word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Keep in sync with `ToneMapper::apply`, a test in display.rs compares them
+fn toneMap(x: vec3<f32>) -> vec3<f32> {
+    if (display.toneMapper == TONE_MAPPER_REINHARD) {
+        return x / (1.0 + x);
+    } else if (display.toneMapper == TONE_MAPPER_ACES) {
+        return clamp(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14), vec3(0.0), vec3(1.0));
+    } else if (display.toneMapper == TONE_MAPPER_AGX) {
+        return agx(x);
+    } else if (display.toneMapper == TONE_MAPPER_UNCHARTED_2) {
+        return clamp(hable(2.0 * x) / hable(vec3(11.2)), vec3(0.0), vec3(1.0));
+    }
+    return clamp(x, vec3(0.0), vec3(1.0));
+}
+
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a: f32 = 0.15;
+    let b: f32 = 0.50;
+    let c: f32 = 0.10;
+    let d: f32 = 0.20;
+    let e: f32 = 0.02;
+    let f: f32 = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+fn agx(x: vec3<f32>) -> vec3<f32> {
+    let inset: mat3x3<f32> = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset: mat3x3<f32> = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let minEv: f32 = -12.47393;
+    let maxEv: f32 = 4.026069;
+
+    let ev: vec3<f32> = clamp(log2(max(inset * x, vec3(1e-10))), vec3(minEv), vec3(maxEv));
+    let v: vec3<f32> = (ev - minEv) / (maxEv - minEv);
+    let v2: vec3<f32> = v * v;
+    let v4: vec3<f32> = v2 * v2;
+    let sigmoid: vec3<f32> = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+    return min(pow(max(outset * sigmoid, vec3(0.0)), vec3(2.2)), vec3(1.0));
+}
+
+fn encodeSrgb(linear: vec3<f32>) -> vec3<f32> {
+    let c: vec3<f32> = clamp(linear, vec3(0.0), vec3(1.0));
+    return select(1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, 12.92 * c, c <= vec3(0.0031308));
 }
@@ -0,0 +1,100 @@
+// Reconstructs pixels from the samples of one frame. Every pixel adds up the
+// samples taken within the filter radius of its centre, each weighted by the
+// filter at its offset, together with the sum of the weights. Dividing one
+// by the other gives the filtered radiance however many samples were taken.
+
+@group(0) @binding(0) var<storage, read> samples: array<Sample>;
+@group(0) @binding(1) var<storage, read_write> accumulation: array<vec4<f32>>;
+@group(0) @binding(2) var colorBuffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(3) var<uniform> pixelFilter: PixelFilter;
+
+const FILTER_BOX: u32 = 0u;
+const FILTER_TENT: u32 = 1u;
+const FILTER_GAUSSIAN: u32 = 2u;
+const FILTER_MITCHELL: u32 = 3u;
+const FILTER_BLACKMAN_HARRIS: u32 = 4u;
+
+const PI: f32 = 3.14159265358979;
+
+struct Sample {
+    radiance: vec3<f32>,
+    // Position inside the pixel the sample was taken in, in [0, 1)
+    offset: vec2<f32>,
+}
+
+struct PixelFilter {
+    kind: u32,
+    radius: f32,
+    // Makes the weights integrate to one
+    normalization: f32,
+    frame: u32,
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size: vec2<u32> = textureDimensions(colorBuffer);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let pixel: vec2<i32> = vec2<i32>(id.xy);
+    let center: vec2<f32> = vec2<f32>(pixel) + 0.5;
+
+    // Samples lie anywhere in their pixel, so pixels up to this far away
+    // can hold one within the radius
+    let reach: i32 = i32(ceil(pixelFilter.radius + 0.5));
+    var sum: vec4<f32> = vec4(0.0);
+    for (var y: i32 = -reach; y <= reach; y++) {
+        for (var x: i32 = -reach; x <= reach; x++) {
+            let neighbour: vec2<i32> = pixel + vec2(x, y);
+            if (any(neighbour < vec2(0)) || any(neighbour >= vec2<i32>(size))) {
+                continue;
+            }
+            let neighbourSample: Sample = samples[u32(neighbour.y) * size.x + u32(neighbour.x)];
+            let offset: vec2<f32> = vec2<f32>(neighbour) + neighbourSample.offset - center;
+            let weight: f32 = pixelFilter.normalization * evaluate(offset.x) * evaluate(offset.y);
+            sum += weight * vec4(neighbourSample.radiance, 1.0);
+        }
+    }
+
+    let index: u32 = id.y * size.x + id.x;
+    var accumulated: vec4<f32> = sum;
+    if (pixelFilter.frame > 0u) {
+        accumulated += accumulation[index];
+    }
+    accumulation[index] = accumulated;
+
+    // Negative lobes can cancel the weights out while few samples were taken
+    var color: vec3<f32> = vec3(0.0);
+    if (accumulated.a != 0.0) {
+        color = accumulated.rgb / accumulated.a;
+    }
+    textureStore(colorBuffer, pixel, vec4(color, 1.0));
+}
+
+// The filter along one axis, `x` pixels from the centre
+fn evaluate(offset: f32) -> f32 {
+    let x: f32 = abs(offset);
+    let radius: f32 = pixelFilter.radius;
+    if (x >= radius) {
+        return 0.0;
+    }
+
+    if (pixelFilter.kind == FILTER_TENT) {
+        return 1.0 - x / radius;
+    } else if (pixelFilter.kind == FILTER_GAUSSIAN) {
+        // A standard deviation of a third of the radius
+        return exp(-4.5 * x * x / (radius * radius)) - exp(-4.5);
+    } else if (pixelFilter.kind == FILTER_MITCHELL) {
+        let b: f32 = 1.0 / 3.0;
+        let c: f32 = 1.0 / 3.0;
+        let t: f32 = 2.0 * x / radius;
+        if (t < 1.0) {
+            return ((12.0 - 9.0 * b - 6.0 * c) * t * t * t + (-18.0 + 12.0 * b + 6.0 * c) * t * t + (6.0 - 2.0 * b)) / 6.0;
+        }
+        return ((-b - 6.0 * c) * t * t * t + (6.0 * b + 30.0 * c) * t * t + (-12.0 * b - 48.0 * c) * t + (8.0 * b + 24.0 * c)) / 6.0;
+    } else if (pixelFilter.kind == FILTER_BLACKMAN_HARRIS) {
+        let t: f32 = 2.0 * PI * (0.5 + 0.5 * x / radius);
+        return 0.35875 - 0.48829 * cos(t) + 0.14128 * cos(2.0 * t) - 0.01168 * cos(3.0 * t);
+    }
+    return 1.0;
+}
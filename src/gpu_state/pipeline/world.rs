@@ -0,0 +1,20 @@
+use bytemuck::{Pod, Zeroable};
+use super::medium::MediumStorage;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct WorldUniform {
+    background: [f32; 3],
+    has_fog: u32,
+    fog: MediumStorage,
+}
+
+impl WorldUniform {
+    pub fn new(background: [f32; 3], fog: Option<MediumStorage>) -> WorldUniform {
+        WorldUniform {
+            background,
+            has_fog: fog.is_some() as u32,
+            fog: fog.unwrap_or_else(MediumStorage::zeroed),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Fixtures shared by the tests comparing rendered images.
+
+use winit::dpi::PhysicalSize;
+use super::{Camera, HeadlessState, Material, Object, RenderOptions, Scene};
+
+/// A red ball on a grey ground under the white sky, with a noisy soft shadow
+/// between them and antialiased edges.
+pub fn ball_on_ground() -> Scene {
+    let mut scene = Scene::new(Camera::new([-4.0, 0.0, 1.0], [1.0, 0.0, -0.2]));
+    scene.add(Object::sphere([0.0, 0.0, -100.0], 100.0, Material::diffuse([0.8, 0.8, 0.8]))).unwrap();
+    scene.add(Object::sphere([0.0, 0.0, 1.0], 1.0, Material::diffuse([0.8, 0.2, 0.2]))).unwrap();
+    scene
+}
+
+/// Runs `f` on a square headless state `size` pixels wide, `None` if GPU
+/// tests are skipped.
+pub fn with_state<T>(size: u32, scene: &Scene, options: &RenderOptions, f: impl FnOnce(&mut HeadlessState) -> T) -> Option<T> {
+    let mut state = HeadlessState::for_test(PhysicalSize::new(size, size), scene, options)?;
+    Some(f(&mut state))
+}
+
+/// The radiance after `samples` samples per pixel.
+pub fn render(size: u32, scene: &Scene, options: &RenderOptions, samples: u32) -> Option<Vec<[f32; 3]>> {
+    with_state(size, scene, options, |state| {
+        state.render(samples);
+        state.read_radiance()
+    })
+}
+
+pub fn rms_error(image: &[[f32; 3]], reference: &[[f32; 3]]) -> f32 {
+    let sum: f32 = image
+        .iter()
+        .zip(reference)
+        .flat_map(|(a, b)| (0..3).map(move |i| (a[i] - b[i]).powi(2)))
+        .sum();
+    (sum / (3 * image.len()) as f32).sqrt()
+}
+
+/// Average over all pixels and channels.
+pub fn mean(image: &[[f32; 3]]) -> f32 {
+    image.iter().flatten().sum::<f32>() / (3 * image.len()) as f32
+}
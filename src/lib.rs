@@ -0,0 +1,181 @@
+mod gpu_state;
+
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use winit::{
+    dpi::PhysicalSize,
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use gpu_state::GpuState;
+
+pub use gpu_state::{
+    HeadlessState, ImageFormat, RadianceImage, KeyBindings, MouseLook, Movement, Backend, RenderOptions, ViewerOptions, Scene, Object, Shape, Material, Medium, DensityGrid, Camera, Projection, FisheyeMapping, Fov, Stereo,
+    StereoLayout, LookMode, Display, ToneMapper, Aov, PostEffects, Bloom, Vignette, Lens, Grain, Filter, PixelFilter, Sampler, CameraPath, CameraKeyframe, ObjectAnimation, Transform, TransformKeyframe,
+};
+
+/// Renders `scene` without opening a window and saves it as a PNG or EXR,
+/// depending on the extension of `path`. Panoramic projections have a fixed
+/// aspect ratio, for those the height is ignored and derived from the width.
+pub async fn render_to_file(
+    scene: &Scene,
+    [width, height]: [u32; 2],
+    samples: u32,
+    options: &RenderOptions,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let format = ImageFormat::from_path(&path)?;
+    let [width, height] = scene.camera.image_size(width, height);
+    let mut state = HeadlessState::with_options(PhysicalSize::new(width, height), scene, options).await?;
+    state.render(samples);
+    state.save(path, format)
+}
+
+/// How `render_animation` turns an animation into numbered images.
+#[derive(Clone, Debug)]
+pub struct SequenceOptions {
+    /// Image size, see `render_to_file` for panoramas.
+    pub size: [u32; 2],
+    pub samples: u32,
+    pub fps: f32,
+    /// First frame to render. Frame `n` shows the scene `n / fps` seconds in.
+    pub start: u32,
+    /// Last frame to render, the end of the animation if `None`.
+    pub end: Option<u32>,
+    pub format: ImageFormat,
+    /// Renders frames again even if their file exists. Otherwise they are
+    /// skipped, so an interrupted render picks up where it stopped.
+    pub overwrite: bool,
+    pub render: RenderOptions,
+}
+
+impl Default for SequenceOptions {
+    fn default() -> SequenceOptions {
+        SequenceOptions {
+            size: [640, 360],
+            samples: 64,
+            fps: 24.0,
+            start: 0,
+            end: None,
+            format: ImageFormat::Png,
+            overwrite: false,
+            render: RenderOptions::default(),
+        }
+    }
+}
+
+/// Renders the scene's animation and the camera flying along `path` to
+/// numbered images in `directory`, until both have finished. The camera
+/// keeps the lens, shutter and projection of the scene's camera.
+pub async fn render_animation(scene: &Scene, path: &CameraPath, options: &SequenceOptions, directory: impl AsRef<Path>) -> Result<()> {
+    if options.fps <= 0.0 {
+        bail!("frame rate has to be positive, not {}", options.fps);
+    }
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)?;
+
+    let duration = path.duration().max(scene.animation_duration());
+    let end = options.end.unwrap_or((duration * options.fps).floor() as u32);
+    if end < options.start {
+        bail!("last frame {} comes before first frame {}", end, options.start);
+    }
+    let frames = options.start..=end;
+
+    let progress = ProgressBar::new(frames.clone().count() as u64);
+    progress.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} frames, {elapsed} elapsed, {eta} left {msg}")?);
+
+    let [width, height] = scene.camera.image_size(options.size[0], options.size[1]);
+    let mut state = HeadlessState::with_options(PhysicalSize::new(width, height), scene, &options.render).await?;
+    let mut skipped = 0;
+    for frame in frames {
+        let file = directory.join(format!("frame_{:04}.{}", frame, options.format.extension()));
+        if file.exists() && !options.overwrite {
+            skipped += 1;
+            progress.set_message(format!("({skipped} already rendered)"));
+            progress.inc(1);
+            continue;
+        }
+
+        let time = frame as f32 / options.fps;
+        let mut camera = scene.camera;
+        path.apply(time, &mut camera);
+        state.set_camera(camera);
+        state.set_time(time);
+        state.render(options.samples);
+        // Frames only get their name once they are complete, so an
+        // interrupted render never leaves a broken frame behind to be skipped
+        let mut partial = file.clone().into_os_string();
+        partial.push(".partial");
+        state.save(&partial, options.format)?;
+        std::fs::rename(&partial, &file).with_context(|| format!("writing {}", file.display()))?;
+        progress.inc(1);
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// Opens the interactive viewer. Camera keyframes recorded in it are saved
+/// to `path_file`, which is also where an existing path is loaded from.
+/// Only returns early, when the window or the renderer can not be created.
+pub async fn run(scene: Scene, bindings: KeyBindings, path_file: Option<PathBuf>, options: ViewerOptions) -> Result<()> {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+    let mut window = WindowBuilder::new();
+    if let Some([width, height]) = options.size {
+        window = window.with_inner_size(PhysicalSize::new(width, height));
+    }
+    let window = window.build(&event_loop)?;
+
+    let camera_path = match &path_file {
+        Some(file) if file.exists() => CameraPath::load(file).unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            CameraPath::new()
+        }),
+        _ => CameraPath::new(),
+    };
+    let mut state = GpuState::new(window, &scene, bindings, camera_path, path_file, &options).await?;
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent {
+            ref event,
+            window_id,
+        } if window_id == state.window().id() && !state.input(event) => {
+            match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    // new_inner_size is &&mut so we have to dereference it twice
+                    state.resize(**new_inner_size);
+                }
+                _ => {}
+            }
+        }
+        Event::DeviceEvent { ref event, .. } => {
+            state.device_input(event);
+        }
+        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+            state.update();
+            match state.render() {
+                Ok(_) => {}
+                // Reconfigure the surface if lost
+                Err(wgpu::SurfaceError::Lost) => state.resize(state.size()),
+                // The system is out of memory, we should probably quit
+                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                // All other errors (Outdated, Timeout) should be resolved by the next frame
+                Err(e) => eprintln!("{:?}", e),
+            }
+        }
+        // Quitting is a rebindable action handled by the state
+        Event::MainEventsCleared if state.exit_requested() => *control_flow = ControlFlow::Exit,
+        Event::MainEventsCleared => {
+            // RedrawRequested will only trigger once, unless we manually
+            // request it.
+            state.window().request_redraw();
+        }
+        _ => {}
+    });
+}
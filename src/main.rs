@@ -1,68 +1,355 @@
-mod gpu_state;
-
-use winit::{
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ray_tracing::{
+    render_animation, render_to_file, run, Backend, CameraPath, Display, Filter, ImageFormat, KeyBindings, MouseLook, Movement, PixelFilter, Sampler,
+    RenderOptions, Scene, SequenceOptions, ToneMapper, ViewerOptions,
 };
-use gpu_state::GpuState;
-
-pub async fn run() {
-    env_logger::init();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
-
-    let mut state = GpuState::new(window).await;
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent {
-            ref event,
-            window_id,
-        } if window_id == state.window().id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        // new_inner_size is &&mut so we have to dereference it twice
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
+
+/// GPU path tracer with an interactive viewer and offline rendering.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Opens a scene in the interactive viewer.
+    View(ViewArgs),
+    /// Renders a scene to an image, or its animation to an image sequence.
+    Render(RenderArgs),
+}
+
+#[derive(Args)]
+struct ViewArgs {
+    /// Scene description in TOML, a built-in random scene if left out.
+    scene: Option<PathBuf>,
+    /// Window width in pixels, needs --height as well.
+    #[arg(long, requires = "height", value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+    /// Window height in pixels, needs --width as well.
+    #[arg(long, requires = "width", value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+    /// Stop accumulating after this many samples per pixel until the view
+    /// changes.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    spp: Option<u32>,
+    /// Key bindings to load instead of the defaults, `bindings.toml` in the
+    /// working directory if it exists.
+    #[arg(long)]
+    bindings: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    present_mode: Option<PresentMode>,
+    /// Directory F12 saves screenshots to, the working directory by default.
+    #[arg(long)]
+    screenshots: Option<PathBuf>,
+    /// Start every new view from a single sample instead of reprojecting
+    /// the previous image.
+    #[arg(long)]
+    no_temporal: bool,
+    /// Degrees the camera turns per count of mouse motion.
+    #[arg(long, default_value_t = MouseLook::default().sensitivity, value_parser = positive)]
+    mouse_sensitivity: f32,
+    /// Look up when the mouse moves down.
+    #[arg(long)]
+    invert_mouse_y: bool,
+    /// Top flying speed in scene units per second.
+    #[arg(long, default_value_t = Movement::default().speed, value_parser = positive)]
+    speed: f32,
+    /// Factor the speed is multiplied by while sprinting.
+    #[arg(long, default_value_t = Movement::default().sprint_multiplier, value_parser = positive)]
+    sprint_multiplier: f32,
+    /// How quickly the camera gets up to speed, per second.
+    #[arg(long, default_value_t = Movement::default().acceleration, value_parser = positive)]
+    acceleration: f32,
+    /// How quickly the camera stops once the keys are released, per second.
+    #[arg(long, default_value_t = Movement::default().damping, value_parser = positive)]
+    damping: f32,
+    #[command(flatten)]
+    renderer: RendererArgs,
+}
+
+#[derive(Args)]
+struct RenderArgs {
+    /// Scene description in TOML, a built-in random scene if left out.
+    scene: Option<PathBuf>,
+    /// Image file ending in .png, .exr or .hdr, or the directory frames are
+    /// written to with --sequence.
+    #[arg(short, long)]
+    output: PathBuf,
+    #[arg(long, default_value_t = 640, value_parser = clap::value_parser!(u32).range(1..))]
+    width: u32,
+    #[arg(long, default_value_t = 360, value_parser = clap::value_parser!(u32).range(1..))]
+    height: u32,
+    /// Samples per pixel.
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..))]
+    spp: u32,
+    /// Render the animation and camera path frame by frame.
+    #[arg(long)]
+    sequence: bool,
+    /// First frame to render.
+    #[arg(long, default_value_t = 0, requires = "sequence")]
+    start: u32,
+    /// Last frame to render, the end of the animation by default.
+    #[arg(long, requires = "sequence")]
+    end: Option<u32>,
+    #[arg(long, default_value_t = 24.0, requires = "sequence", value_parser = positive)]
+    fps: f32,
+    #[arg(long, value_enum, default_value_t = Format::Png, requires = "sequence")]
+    format: Format,
+    /// Render frames again even if their file already exists.
+    #[arg(long, requires = "sequence")]
+    overwrite: bool,
+    #[command(flatten)]
+    renderer: RendererArgs,
+}
+
+#[derive(Args)]
+struct RendererArgs {
+    /// Longest path traced, counting surface bounces and scattering events.
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=1024))]
+    max_bounces: u32,
+    /// Seed of the random numbers, equal seeds give identical images.
+    #[arg(long, default_value_t = 0)]
+    seed: u32,
+    #[arg(long, value_enum, default_value_t = BackendArg::Auto)]
+    backend: BackendArg,
+    /// Exposure compensation in stops, for the viewer and PNGs.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f32,
+    /// Curve mapping bright radiance into the displayable range.
+    #[arg(long, value_enum, default_value_t = ToneMapperArg::Clamp)]
+    tone_mapper: ToneMapperArg,
+    /// Filter the noise out of the image, guided by normals, depth and
+    /// albedo.
+    #[arg(long)]
+    denoise: bool,
+    /// Reconstruction filter spreading every sample over nearby pixels.
+    #[arg(long, value_enum, default_value_t = FilterArg::Box)]
+    filter: FilterArg,
+    /// Radius of the reconstruction filter in pixels, depends on the filter
+    /// by default.
+    #[arg(long, value_parser = positive)]
+    filter_radius: Option<f32>,
+    /// Sequence the random numbers of every path are drawn from.
+    #[arg(long, value_enum, default_value_t = SamplerArg::Pcg)]
+    sampler: SamplerArg,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum FilterArg {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum SamplerArg {
+    Pcg,
+    Sobol,
+    BlueNoise,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ToneMapperArg {
+    Clamp,
+    Reinhard,
+    Aces,
+    Agx,
+    Uncharted2,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum BackendArg {
+    Auto,
+    Vulkan,
+    Gl,
+    /// CPU rasteriser such as llvmpipe, slow but works without a GPU.
+    Software,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum PresentMode {
+    /// Waits for vertical blank, never tears.
+    Fifo,
+    /// Newest frame at vertical blank, lower latency without tearing.
+    Mailbox,
+    /// Shows frames right away, may tear.
+    Immediate,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    Png,
+    Exr,
+    Hdr,
+}
+
+fn positive(text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        Ok(_) => Err("has to be positive".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl RendererArgs {
+    fn options(&self) -> RenderOptions {
+        RenderOptions {
+            backend: match self.backend {
+                BackendArg::Auto => Backend::Auto,
+                BackendArg::Vulkan => Backend::Vulkan,
+                BackendArg::Gl => Backend::Gl,
+                BackendArg::Software => Backend::Software,
+            },
+            max_bounces: self.max_bounces,
+            seed: self.seed,
+            display: Display {
+                exposure: self.exposure,
+                tone_mapper: match self.tone_mapper {
+                    ToneMapperArg::Clamp => ToneMapper::Clamp,
+                    ToneMapperArg::Reinhard => ToneMapper::Reinhard,
+                    ToneMapperArg::Aces => ToneMapper::AcesFilmic,
+                    ToneMapperArg::Agx => ToneMapper::AgX,
+                    ToneMapperArg::Uncharted2 => ToneMapper::Uncharted2,
+                },
+            },
+            denoise: self.denoise,
+            temporal: false,
+            pixel_filter: {
+                let filter = match self.filter {
+                    FilterArg::Box => Filter::Box,
+                    FilterArg::Tent => Filter::Tent,
+                    FilterArg::Gaussian => Filter::Gaussian,
+                    FilterArg::Mitchell => Filter::Mitchell,
+                    FilterArg::BlackmanHarris => Filter::BlackmanHarris,
+                };
+                let pixel_filter = PixelFilter::new(filter);
+                match self.filter_radius {
+                    Some(radius) => pixel_filter.with_radius(radius),
+                    None => pixel_filter,
                 }
-            }
+            },
+            sampler: match self.sampler {
+                SamplerArg::Pcg => Sampler::Pcg,
+                SamplerArg::Sobol => Sampler::Sobol,
+                SamplerArg::BlueNoise => Sampler::BlueNoise,
+            },
         }
-        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-            state.update();
-            match state.render() {
-                Ok(_) => {}
-                // Reconfigure the surface if lost
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size()),
-                // The system is out of memory, we should probably quit
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                // All other errors (Outdated, Timeout) should be resolved by the next frame
-                Err(e) => eprintln!("{:?}", e),
-            }
-        }
-        Event::MainEventsCleared => {
-            // RedrawRequested will only trigger once, unless we manually
-            // request it.
-            state.window().request_redraw();
-        }
-        _ => {}
-    });
+    }
 }
 
 fn main() {
-    pollster::block_on(run());
+    if let Err(e) = try_main(Cli::parse()) {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn try_main(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::View(args) => view(args),
+        Command::Render(args) => render(args),
+    }
+}
+
+fn load_scene(path: Option<&Path>) -> Result<(Scene, PathBuf)> {
+    let scene = match path {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+    // Camera paths live next to their scene
+    let path_file = CameraPath::path_for_scene(path.unwrap_or("scene".as_ref()));
+    Ok((scene, path_file))
+}
+
+fn view(args: ViewArgs) -> Result<()> {
+    let (scene, path_file) = load_scene(args.scene.as_deref())?;
+    let bindings = match &args.bindings {
+        Some(path) => KeyBindings::load(path)?,
+        None => KeyBindings::load_or_default("bindings.toml")?,
+    };
+    let options = ViewerOptions {
+        render: RenderOptions {
+            temporal: !args.no_temporal,
+            ..args.renderer.options()
+        },
+        size: args.width.zip(args.height).map(|(width, height)| [width, height]),
+        samples: args.spp,
+        present_mode: args.present_mode.map(|mode| match mode {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }),
+        scene_file: args.scene,
+        screenshot_directory: args.screenshots,
+        mouse_look: MouseLook {
+            sensitivity: args.mouse_sensitivity,
+            invert_y: args.invert_mouse_y,
+        },
+        movement: Movement {
+            speed: args.speed,
+            sprint_multiplier: args.sprint_multiplier,
+            acceleration: args.acceleration,
+            damping: args.damping,
+        },
+    };
+    pollster::block_on(run(scene, bindings, Some(path_file), options))
+}
+
+fn render(args: RenderArgs) -> Result<()> {
+    let (scene, path_file) = load_scene(args.scene.as_deref())?;
+    let size = [args.width, args.height];
+    if !args.sequence {
+        // Fail before rendering rather than after
+        ImageFormat::from_path(&args.output)?;
+        return pollster::block_on(render_to_file(&scene, size, args.spp, &args.renderer.options(), &args.output));
+    }
+
+    let camera_path = if path_file.exists() { CameraPath::load(&path_file)? } else { CameraPath::new() };
+    let options = SequenceOptions {
+        size,
+        samples: args.spp,
+        fps: args.fps,
+        start: args.start,
+        end: args.end,
+        format: match args.format {
+            Format::Png => ImageFormat::Png,
+            Format::Exr => ImageFormat::Exr,
+            Format::Hdr => ImageFormat::Hdr,
+        },
+        overwrite: args.overwrite,
+        render: args.renderer.options(),
+    };
+    pollster::block_on(render_animation(&scene, &camera_path, &options, &args.output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_is_consistent() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        assert!(Cli::try_parse_from(["rt", "render", "-o", "a.png", "--spp", "0"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "render", "-o", "a.png", "--fps", "30"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "render", "-o", "out", "--sequence", "--fps", "-1"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--width", "800"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--backend", "metal"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--mouse-sensitivity", "0"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--mouse-sensitivity", "0.3", "--invert-mouse-y"]).is_ok());
+        assert!(Cli::try_parse_from(["rt", "view", "--speed", "-2"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--damping", "0"]).is_err());
+        assert!(Cli::try_parse_from(["rt", "view", "--speed", "10", "--sprint-multiplier", "2", "--acceleration", "4"]).is_ok());
+        assert!(Cli::try_parse_from(["rt", "view", "--width", "800", "--height", "600", "--backend", "gl"]).is_ok());
+    }
 }